@@ -0,0 +1,92 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use hecto::{Document, Position, Row, SearchDirection};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Operation
+{
+    Insert { x: u8, y: u8, c: char },
+    Delete { x: u8, y: u8 },
+    Indent { y: u8 },
+    Dedent { y: u8 },
+    Find { x: u8, y: u8, query: String, backward: bool },
+    MatchingBracket { x: u8, y: u8 },
+    SplitRow { y: u8, at: u8 },
+    Render { y: u8, start: u8, end: u8, show_whitespace: bool },
+    Undo,
+}
+
+fn position(x: u8, y: u8) -> Position
+{
+    Position { x: x.into(), y: y.into() }
+}
+
+fn direction(backward: bool) -> SearchDirection
+{
+    if backward
+    {
+        SearchDirection::Backward
+    }
+    else
+    {
+        SearchDirection::Forward
+    }
+}
+
+// None of the operations may panic, and every row must keep its cached length
+// in sync with its actual grapheme count.
+fuzz_target!(|operations: Vec<Operation>| {
+    let mut document = Document::default();
+    for operation in operations
+    {
+        match operation
+        {
+            Operation::Insert { x, y, c } => {
+                document.checkpoint(&position(x, y));
+                document.insert(&position(x, y), c);
+            },
+            Operation::Delete { x, y } => document.delete(&position(x, y)),
+            Operation::Indent { y } => {
+                document.indent(y.into());
+            },
+            Operation::Dedent { y } => {
+                document.dedent(y.into());
+            },
+            Operation::Find { x, y, query, backward } => {
+                if let Some(found) = document.find(&query, &position(x, y), direction(backward))
+                {
+                    assert!(found.y < document.len());
+                }
+            },
+            Operation::MatchingBracket { x, y } => {
+                document.matching_bracket(&position(x, y), 0..document.len());
+            },
+            Operation::SplitRow { y, at } => {
+                if let Some(row) = document.row(y.into())
+                {
+                    let mut row = Row::from(std::str::from_utf8(row.as_bytes()).unwrap());
+                    let tail = row.split(at.into());
+                    assert_eq!(row.len(), row.graphemes().count());
+                    assert_eq!(tail.len(), tail.graphemes().count());
+                }
+            },
+            Operation::Render { y, start, end, show_whitespace } => {
+                if let Some(row) = document.row(y.into())
+                {
+                    row.render(start.into(), end.into(), show_whitespace);
+                    row.width(start.into(), end.into(), show_whitespace);
+                }
+            },
+            Operation::Undo => {
+                document.undo();
+            },
+        }
+        for y in 0..document.len()
+        {
+            let row = document.row(y).unwrap();
+            assert_eq!(row.len(), row.graphemes().count());
+        }
+    }
+});
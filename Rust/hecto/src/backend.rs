@@ -0,0 +1,344 @@
+use crate::Event;
+use crate::Position;
+use signal_hook::consts::{SIGHUP, SIGTERM, SIGWINCH};
+use signal_hook::iterator::Signals;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::panic;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use termion::{event, input::TermRead, screen::{ToAlternateScreen, ToMainScreen}};
+
+// Button presses, drags and wheel movements, reported in SGR encoding, and
+// the terminal gaining focus.
+const ENABLE_MOUSE: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1006h\x1b[?1004h";
+const DISABLE_MOUSE: &str = "\x1b[?1004l\x1b[?1006l\x1b[?1002l\x1b[?1000l";
+const FOCUS_IN: &[u8] = b"\x1b[I";
+const IDLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The device the editor draws to and reads input from.
+pub trait Backend
+{
+    /// Full size of the screen as (width, height).
+    fn size(&self) -> Result<(u16, u16), std::io::Error>;
+
+    /// Replaces each of the `lines` as (row, content), clearing the screen
+    /// first if `clear` is set, then places the cursor.
+    fn draw(&mut self, clear: bool, lines: &[(usize, &str)], cursor: &Position) -> Result<(), std::io::Error>;
+
+    /// Waits for the next key press, mouse event or resize.
+    fn read_event(&mut self) -> Result<Event, std::io::Error>;
+}
+
+/// Draws to the controlling terminal on the alternate screen, in raw mode and
+/// with mouse reporting enabled, until it is dropped. The terminal is opened
+/// as `/dev/tty`, so standard input and output stay free for piped data.
+pub struct TermionBackend
+{
+    events: Receiver<Result<Event, std::io::Error>>,
+    tty: File,
+    original_mode: libc::termios
+}
+
+fn terminal_mode(tty: &File) -> Result<libc::termios, std::io::Error>
+{
+    let mut mode = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: `tcgetattr` fully initializes `mode` when it succeeds.
+    if unsafe { libc::tcgetattr(tty.as_raw_fd(), mode.as_mut_ptr()) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: checked above that `tcgetattr` succeeded.
+    Ok(unsafe { mode.assume_init() })
+}
+
+// termion's raw mode only applies to stdout, which may be a pipe here.
+fn set_terminal_mode(tty: &File, mode: &libc::termios) -> Result<(), std::io::Error>
+{
+    // SAFETY: `mode` is a valid termios and the descriptor is open.
+    if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, mode) } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl TermionBackend
+{
+    /// Opens the controlling terminal and switches it to raw mode on the
+    /// alternate screen.
+    pub fn new() -> Result<Self, std::io::Error>
+    {
+        let (sender, events) = mpsc::channel();
+        Self::spawn_key_reader(termion::get_tty()?, sender.clone());
+        Self::spawn_signal_listener(sender)?;
+        let mut tty = termion::get_tty()?;
+        let original_mode = terminal_mode(&tty)?;
+        Self::install_panic_hook(tty.try_clone()?, original_mode);
+        let mut raw_mode = original_mode;
+        // SAFETY: `raw_mode` is a valid termios.
+        unsafe { libc::cfmakeraw(&mut raw_mode) };
+        set_terminal_mode(&tty, &raw_mode)?;
+        write!(tty, "{}{}", ToAlternateScreen, ENABLE_MOUSE)?;
+        tty.flush()?;
+        Ok(Self {
+            events,
+            tty,
+            original_mode
+        })
+    }
+
+    // Leaves the alternate screen and restores the original terminal modes
+    // before the default hook prints the panic message, so that the message
+    // ends up readable on the main screen.
+    fn install_panic_hook(tty: File, original_mode: libc::termios)
+    {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            Self::leave(&tty);
+            let _ = set_terminal_mode(&tty, &original_mode);
+            default_hook(info);
+        }));
+    }
+
+    fn leave(mut tty: &File)
+    {
+        let _ = write!(tty, "{}{}{}", DISABLE_MOUSE, ToMainScreen, termion::cursor::Show);
+        let _ = tty.flush();
+    }
+
+    // Input is read on its own thread so that a resize can interrupt a
+    // blocking wait for input.
+    fn spawn_key_reader(tty: File, sender: Sender<Result<Event, std::io::Error>>)
+    {
+        thread::spawn(move || {
+            for input in tty.events()
+            {
+                let event = match input
+                {
+                    Ok(event::Event::Key(key)) => Ok(Event::Key(key)),
+                    Ok(event::Event::Mouse(mouse)) => Ok(Event::Mouse(mouse)),
+                    Ok(event::Event::Unsupported(sequence)) if sequence == FOCUS_IN => Ok(Event::Focus),
+                    Ok(event::Event::Unsupported(_)) => continue,
+                    Err(error) => Err(error),
+                };
+                if sender.send(event).is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    // Turns resizes into events, and being told to terminate or losing the
+    // terminal into an error, which unwinds the editor like any other so that
+    // the terminal is restored and lock markers are removed on the way out.
+    fn spawn_signal_listener(sender: Sender<Result<Event, std::io::Error>>) -> Result<(), std::io::Error>
+    {
+        let mut signals = Signals::new([SIGWINCH, SIGTERM, SIGHUP])?;
+        thread::spawn(move || {
+            for signal in signals.forever()
+            {
+                let event = match signal
+                {
+                    SIGWINCH => Ok(Event::Resize),
+                    SIGHUP => Err(io::Error::new(io::ErrorKind::Interrupted, "terminal hung up")),
+                    _ => Err(io::Error::new(io::ErrorKind::Interrupted, "terminated")),
+                };
+                if sender.send(event).is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn to_screen(coordinate: usize) -> u16
+    {
+        coordinate.saturating_add(1).try_into().unwrap_or(u16::MAX)
+    }
+}
+
+impl Backend for TermionBackend
+{
+    // Asks the tty itself, as `termion::terminal_size` only looks at stdout.
+    fn size(&self) -> Result<(u16, u16), std::io::Error>
+    {
+        let mut size = std::mem::MaybeUninit::<libc::winsize>::uninit();
+        // SAFETY: `TIOCGWINSZ` fills in a `winsize` when it succeeds.
+        if unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, size.as_mut_ptr()) } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: checked above that the ioctl succeeded.
+        let size = unsafe { size.assume_init() };
+        Ok((size.ws_col, size.ws_row))
+    }
+
+    // Everything for one frame goes out in a single write.
+    fn draw(&mut self, clear: bool, lines: &[(usize, &str)], cursor: &Position) -> Result<(), std::io::Error>
+    {
+        let mut output = termion::cursor::Hide.to_string();
+        if clear
+        {
+            output.push_str(termion::clear::All.as_ref());
+        }
+        for (y, line) in lines
+        {
+            output.push_str(&format!(
+                "{}{}{}",
+                termion::cursor::Goto(1, Self::to_screen(*y)),
+                line,
+                termion::clear::UntilNewline
+            ));
+        }
+        output.push_str(&format!(
+            "{}{}",
+            termion::cursor::Goto(Self::to_screen(cursor.x), Self::to_screen(cursor.y)),
+            termion::cursor::Show
+        ));
+
+        self.tty.write_all(output.as_bytes())?;
+        self.tty.flush()
+    }
+
+    fn read_event(&mut self) -> Result<Event, std::io::Error>
+    {
+        match self.events.recv_timeout(IDLE_INTERVAL)
+        {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => Ok(Event::Idle),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input closed")),
+        }
+    }
+}
+
+impl Drop for TermionBackend
+{
+    fn drop(&mut self)
+    {
+        Self::leave(&self.tty);
+        let _ = set_terminal_mode(&self.tty, &self.original_mode);
+    }
+}
+
+pub use grid_backend::{Grid, GridBackend};
+
+mod grid_backend
+{
+    use super::Backend;
+    use crate::Event;
+    use crate::Position;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::rc::Rc;
+
+    /// The text shown on a `GridBackend`, one string per screen row, without
+    /// any styling.
+    #[derive(Default)]
+    pub struct Grid
+    {
+        /// The rows of the screen, top to bottom.
+        pub lines: Vec<String>,
+        /// Where the cursor was last placed, in screen coordinates.
+        pub cursor: Position
+    }
+
+    /// Plays back a scripted list of events and records what is drawn into a
+    /// `Grid`, so that the editor can be hosted or tested without a terminal.
+    pub struct GridBackend
+    {
+        width: u16,
+        height: u16,
+        events: VecDeque<Event>,
+        grid: Rc<RefCell<Grid>>
+    }
+
+    impl GridBackend
+    {
+        /// A `width` by `height` screen that returns `events` in order and
+        /// then reports the end of input.
+        pub fn new(width: u16, height: u16, events: Vec<Event>) -> Self
+        {
+            Self {
+                width,
+                height,
+                events: events.into(),
+                grid: Rc::new(RefCell::new(Grid {
+                    lines: vec![String::new(); height as usize],
+                    cursor: Position::default()
+                }))
+            }
+        }
+
+        /// A handle to the drawn grid that stays valid after the backend is
+        /// moved into a `Terminal`.
+        pub fn grid(&self) -> Rc<RefCell<Grid>>
+        {
+            Rc::clone(&self.grid)
+        }
+
+        // Removes escape sequences (colors, styles) from a drawn line.
+        fn strip_escapes(line: &str) -> String
+        {
+            let mut result = String::new();
+            let mut chars = line.chars();
+            while let Some(c) = chars.next()
+            {
+                if c == '\x1b'
+                {
+                    if chars.next() == Some('[')
+                    {
+                        for c in chars.by_ref()
+                        {
+                            if ('\x40'..='\x7e').contains(&c)
+                            {
+                                break;
+                            }
+                        }
+                    }
+                    continue;
+                }
+                result.push(c);
+            }
+            result
+        }
+    }
+
+    impl Backend for GridBackend
+    {
+        fn size(&self) -> Result<(u16, u16), io::Error>
+        {
+            Ok((self.width, self.height))
+        }
+
+        fn draw(&mut self, clear: bool, lines: &[(usize, &str)], cursor: &Position) -> Result<(), io::Error>
+        {
+            let mut grid = self.grid.borrow_mut();
+            if clear
+            {
+                grid.lines = vec![String::new(); self.height as usize];
+            }
+            for (y, line) in lines
+            {
+                if let Some(target) = grid.lines.get_mut(*y)
+                {
+                    *target = Self::strip_escapes(line);
+                }
+            }
+            grid.cursor = cursor.clone();
+            Ok(())
+        }
+
+        fn read_event(&mut self) -> Result<Event, io::Error>
+        {
+            self.events
+                .pop_front()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "end of script"))
+        }
+    }
+}
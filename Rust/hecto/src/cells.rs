@@ -0,0 +1,47 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// The number of cells `text` takes on the terminal.
+pub fn width(text: &str) -> usize
+{
+    text.width()
+}
+
+// The longest start of `text` that fits into `width` cells, never splitting
+// a grapheme cluster.
+pub fn truncate(text: &str, width: usize) -> String
+{
+    let mut used = 0;
+    text.graphemes(true)
+        .take_while(|grapheme| {
+            used += grapheme.width();
+            used <= width
+        })
+        .collect()
+}
+
+// `left` and `right` on one line `width` cells wide, with `right` against the
+// right edge. When both do not fit, `right` follows `left` and gets cut off.
+pub fn align(left: &str, right: &str, width: usize) -> String
+{
+    let padding = width.saturating_sub(self::width(left) + self::width(right)).max(1);
+    let line = truncate(&format!("{}{}{}", left, " ".repeat(padding), right), width);
+    let filled = self::width(&line);
+    format!("{}{}", line, " ".repeat(width.saturating_sub(filled)))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn aligns_by_display_width()
+    {
+        assert_eq!(align("日本", "1/2", 10), "日本   1/2");
+        assert_eq!(align("left", "right", 8), "left rig");
+        assert_eq!(align("日本語", "", 5), "日本 ");
+        assert_eq!(truncate("e\u{301}x", 1), "e\u{301}");
+        assert_eq!(width("日本"), 4);
+    }
+}
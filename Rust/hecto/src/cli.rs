@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+
+/// The help text printed by `--help` and after argument errors.
+pub const USAGE: &str = "\
+Usage: hecto [OPTIONS] [+LINE] [FILE]...
+
+Edit each FILE in its own buffer. A FILE that does not exist yet is created
+when it is first saved, and `-` reads the buffer from standard input.
+
+Options:
+  +LINE              Put the cursor on LINE of the first file
+  -c, --config PATH  Read settings from PATH instead of the default config
+  -R, --readonly     Open the files without allowing them to be written
+  -o, --stdout       Write the buffer read from `-`, or else the first one, to
+                     standard output on exit
+  -h, --help         Print this help and exit
+  -V, --version      Print the version and exit
+  --                 Treat all following arguments as files
+";
+
+/// The parsed command line.
+#[derive(Default, Debug, PartialEq)]
+pub struct Args
+{
+    /// The files to open, in order, where `-` stands for standard input.
+    pub files: Vec<String>,
+    /// The 1-based line to put the cursor on, from `+LINE`.
+    pub line: Option<usize>,
+    /// A config file to read instead of the default one.
+    pub config: Option<PathBuf>,
+    /// Whether the files are opened read-only.
+    pub readonly: bool,
+    /// Whether a buffer is written to standard output on exit.
+    pub stdout: bool,
+    /// Whether to print the usage and exit.
+    pub help: bool,
+    /// Whether to print the version and exit.
+    pub version: bool
+}
+
+impl Args
+{
+    /// Parses the arguments that follow the program name.
+    pub fn parse<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        let mut only_files = false;
+        while let Some(arg) = args.next()
+        {
+            if only_files || arg == "-" || !(arg.starts_with('-') || arg.starts_with('+'))
+            {
+                parsed.files.push(arg);
+                continue;
+            }
+            if let Some(line) = arg.strip_prefix('+')
+            {
+                let line = line
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid line number `{}`", line))?;
+                parsed.line = Some(line);
+                continue;
+            }
+            let (name, value) = match arg.split_once('=')
+            {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            if value.is_some() && name != "--config"
+            {
+                return Err(format!("option `{}` does not take a value", name));
+            }
+            match name
+            {
+                "--" => only_files = true,
+                "-h" | "--help" => parsed.help = true,
+                "-V" | "--version" => parsed.version = true,
+                "-R" | "--readonly" => parsed.readonly = true,
+                "-o" | "--stdout" => parsed.stdout = true,
+                "-c" | "--config" => {
+                    let path = value
+                        .or_else(|| args.next())
+                        .ok_or_else(|| format!("option `{}` needs a path", name))?;
+                    parsed.config = Some(PathBuf::from(path));
+                },
+                _ => return Err(format!("unknown option `{}`", name)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The buffer `--stdout` writes, counted in the order of `files`: the one
+    /// read from standard input if there is one, otherwise the first.
+    pub fn stdout_buffer(&self) -> usize
+    {
+        self.files.iter().position(|file| file == "-").unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String>
+    {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_files_and_options()
+    {
+        let args = parse(&["-R", "+12", "a.rs", "--config=x.conf", "-", "b.rs", "-o"]).unwrap();
+        assert_eq!(args.files, ["a.rs", "-", "b.rs"]);
+        assert_eq!(args.line, Some(12));
+        assert_eq!(args.config, Some(PathBuf::from("x.conf")));
+        assert!(args.readonly && args.stdout && !args.help && !args.version);
+        assert_eq!(args.stdout_buffer(), 1);
+        assert_eq!(parse(&["a.rs", "-o"]).unwrap().stdout_buffer(), 0);
+
+        let args = parse(&["--config", "y.conf", "--", "--help", "+3"]).unwrap();
+        assert_eq!(args.files, ["--help", "+3"]);
+        assert_eq!(args.config, Some(PathBuf::from("y.conf")));
+        assert_eq!(args.line, None);
+    }
+
+    #[test]
+    fn rejects_bad_arguments()
+    {
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["+abc"]).is_err());
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--help=yes"]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const GLOBAL_SECTION: &str = "";
+// Read before the config file, which can override any of these.
+const BUILT_IN: &str = "
+[makefile]
+# Recipes have to be indented with tabs.
+tabs_to_spaces = false
+";
+
+/// Transforms applied to a document when it is saved.
+pub struct SaveOptions
+{
+    /// Removes spaces and tabs at the end of every line.
+    pub strip_trailing_whitespace: bool,
+    /// Ends the file with exactly one newline.
+    pub ensure_final_newline: bool,
+    /// Expands tabs to spaces.
+    pub tabs_to_spaces: bool
+}
+
+impl Default for SaveOptions
+{
+    fn default() -> Self
+    {
+        Self {
+            strip_trailing_whitespace: true,
+            ensure_final_newline: true,
+            tabs_to_spaces: false
+        }
+    }
+}
+
+/// Settings read from a file of `key = value` lines. Keys before any header
+/// apply to every file; keys under a `[file type]` header (e.g. `[makefile]`)
+/// only apply to files of that type and take precedence. Some file types come
+/// with built-in settings, such as keeping tabs in makefiles.
+pub struct Config
+{
+    sections: HashMap<String, HashMap<String, String>>
+}
+
+impl Default for Config
+{
+    fn default() -> Self
+    {
+        let mut config = Self { sections: HashMap::new() };
+        config.read(BUILT_IN).expect("built-in settings parse");
+        config
+    }
+}
+
+impl Config
+{
+    /// `$XDG_CONFIG_HOME/hecto/config`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf>
+    {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(base.join("hecto").join("config"))
+    }
+
+    /// Reads and parses the config file at `path`.
+    pub fn load(path: &Path) -> Result<Self, String>
+    {
+        let contents = fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        Self::parse(&contents).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Parses `key = value` lines, grouped into `[filetype]` sections.
+    pub fn parse(contents: &str) -> Result<Self, String>
+    {
+        let mut config = Self::default();
+        config.read(contents)?;
+        Ok(config)
+    }
+
+    fn read(&mut self, contents: &str) -> Result<(), String>
+    {
+        let mut section = GLOBAL_SECTION.to_string();
+        for (number, line) in contents.lines().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']'))
+            {
+                section = name.trim().to_lowercase();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `key = value`", number + 1))?;
+            self.sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(())
+    }
+
+    /// The value of `key` for `file_type`, or the global value.
+    pub fn get(&self, file_type: &str, key: &str) -> Option<&str>
+    {
+        [file_type.to_lowercase(), GLOBAL_SECTION.to_string()]
+            .iter()
+            .find_map(|section| self.sections.get(section)?.get(key))
+            .map(String::as_str)
+    }
+
+    /// Like `get`, for `true`/`yes`/`on` and `false`/`no`/`off` values.
+    pub fn get_bool(&self, file_type: &str, key: &str) -> Option<bool>
+    {
+        match self.get(file_type, key)?
+        {
+            "true" | "yes" | "on" => Some(true),
+            "false" | "no" | "off" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The transforms to apply when saving a file of `file_type`.
+    pub fn save_options(&self, file_type: &str) -> SaveOptions
+    {
+        let defaults = SaveOptions::default();
+        SaveOptions {
+            strip_trailing_whitespace: self
+                .get_bool(file_type, "strip_trailing_whitespace")
+                .unwrap_or(defaults.strip_trailing_whitespace),
+            ensure_final_newline: self
+                .get_bool(file_type, "ensure_final_newline")
+                .unwrap_or(defaults.ensure_final_newline),
+            tabs_to_spaces: self
+                .get_bool(file_type, "tabs_to_spaces")
+                .unwrap_or(defaults.tabs_to_spaces)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn keeps_tabs_in_makefiles_unless_configured()
+    {
+        let config = Config::parse("tabs_to_spaces = true").unwrap();
+        assert!(config.save_options("Rust").tabs_to_spaces);
+        assert!(!config.save_options("Makefile").tabs_to_spaces);
+        let config = Config::parse("[makefile]\ntabs_to_spaces = yes").unwrap();
+        assert!(config.save_options("Makefile").tabs_to_spaces);
+    }
+}
@@ -1,53 +1,333 @@
+use crate::files;
+use crate::FileType;
+use crate::Listing;
+use crate::lock::{FileLock, LockOwner};
 use crate::Position;
+use crate::row::TAB_WIDTH;
 use crate::Row;
+use crate::SaveOptions;
 use crate::SearchDirection;
+use similar::TextDiff;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::ffi::CString;
 use std::fs;
-use std::io::{Error, Write};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Error, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-#[derive(Default)]
+const DEFAULT_INDENT: &str = "    ";
+const UNDO_LIMIT: usize = 100;
+pub const BRACKET_PAIRS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+
+/// How the file of a document changed on disk behind its back.
+#[derive(Clone, PartialEq, Debug)]
+pub enum DiskChange
+{
+    /// The file was written, or appeared where there was none, and now
+    /// looks like this.
+    Modified(DiskState),
+    /// The file was removed.
+    Deleted
+}
+
+// The contents before an edit, and where the cursor was.
+struct Snapshot
+{
+    rows: Vec<Row>,
+    at: Position,
+    version: u64
+}
+
+// Whether this process may write to `filename`. Asking the kernel takes
+// ownership, groups and read-only mounts into account without opening the
+// file, which could block on a FIFO.
+fn is_writable(filename: &str) -> bool
+{
+    let Ok(path) = CString::new(filename) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid NUL-terminated string.
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// What a file on disk looked like when it was last read or written.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DiskState
+{
+    modified: Option<SystemTime>,
+    size: u64,
+    hash: u64
+}
+
+impl DiskState
+{
+    fn new(metadata: &fs::Metadata, contents: &str) -> Self
+    {
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        Self {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            hash: hasher.finish()
+        }
+    }
+
+    // Only reads the file when its modification time or size changed, and
+    // ignores changes that left the contents the same.
+    fn read_if_changed(&self, filename: &str) -> Option<Self>
+    {
+        let metadata = fs::metadata(filename).ok()?;
+        if metadata.modified().ok() == self.modified && metadata.len() == self.size
+        {
+            return None;
+        }
+        let state = Self::new(&metadata, &fs::read_to_string(filename).ok()?);
+        (state.hash != self.hash).then_some(state)
+    }
+}
+
+// Writes `contents` to `filename`, creating missing parent directories.
+fn write_file(filename: &str, contents: &str) -> Result<(), Error>
+{
+    if let Some(parent) = Path::new(filename).parent().filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(filename, contents)
+}
+
+/// A buffer of rows, optionally backed by a file, with undo history.
+/// Read-only documents ignore every edit and refuse to be saved.
 pub struct Document
 {
     rows: Vec<Row>,
+    /// The file the document is saved to, if any.
     pub filename: Option<String>,
-    dirty: bool
+    // Identifies the contents: every change takes a new number, and undo
+    // returns to the number of the contents it restores.
+    version: u64,
+    versions: u64,
+    saved_version: u64,
+    read_only: bool,
+    trailing_newline: bool,
+    // Whether lines are written ending in CR LF rather than LF. Files mixing
+    // both get the ending most of their lines have, LF on a tie.
+    crlf: bool,
+    file_type: FileType,
+    indent_unit: String,
+    undo_stack: VecDeque<Snapshot>,
+    // Where typing has to continue to join the last snapshot.
+    typing_at: Option<Position>,
+    // Rows added or removed since `take_line_shifts` was last called.
+    line_shifts: Vec<(usize, isize)>,
+    disk_state: Option<DiskState>,
+    dismissed_change: Option<DiskChange>,
+    lock: Option<FileLock>,
+    lock_owner: Option<LockOwner>,
+    listing: Option<Listing>
+}
+
+impl Default for Document
+{
+    fn default() -> Self
+    {
+        Self {
+            rows: Vec::new(),
+            filename: None,
+            version: 0,
+            versions: 0,
+            saved_version: 0,
+            read_only: false,
+            trailing_newline: true,
+            crlf: false,
+            file_type: FileType::default(),
+            indent_unit: DEFAULT_INDENT.to_string(),
+            undo_stack: VecDeque::new(),
+            typing_at: None,
+            line_shifts: Vec::new(),
+            disk_state: None,
+            dismissed_change: None,
+            lock: None,
+            lock_owner: None,
+            listing: None
+        }
+    }
 }
 
 impl Document
 {
+    /// Reads `filename` into a new document that saves back to it. The file
+    /// is not marked as being edited until `lock` is called.
     pub fn open(filename: &str) -> Result<Self, std::io::Error>
+    {
+        Self::read(filename)
+    }
+
+    fn read(filename: &str) -> Result<Self, std::io::Error>
     {
         let contents = fs::read_to_string(filename)?;
-        let mut rows = Vec::new();
-        for value in contents.lines()
+        let mut document = Self::from_text(&contents);
+        document.filename = Some(filename.to_string());
+        document.file_type = FileType::from(filename);
+        document.disk_state = Some(DiskState::new(&fs::metadata(filename)?, &contents));
+        document.read_only = !is_writable(filename);
+        Ok(document)
+    }
+
+    /// An empty document that will be saved to `filename`, which does not
+    /// exist yet.
+    pub fn new_file(filename: &str) -> Self
+    {
+        Self {
+            filename: Some(filename.to_string()),
+            file_type: FileType::from(filename),
+            ..Self::default()
+        }
+    }
+
+    /// Reads everything from `reader`, e.g. standard input, into a document
+    /// that is not backed by any file.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, std::io::Error>
+    {
+        Ok(Self::from_text(&io::read_to_string(reader)?))
+    }
+
+    /// A document holding `contents`, not backed by any file.
+    pub fn from_text(contents: &str) -> Self
+    {
+        Self {
+            rows: contents.lines().map(Row::from).collect(),
+            trailing_newline: contents.is_empty() || contents.ends_with('\n'),
+            crlf: Self::detect_crlf(contents),
+            indent_unit: Self::detect_indent(contents),
+            ..Self::default()
+        }
+    }
+
+    /// A read-only listing of the entries in `dir`.
+    pub fn directory_listing(dir: &Path) -> Result<Self, std::io::Error>
+    {
+        let dir = fs::canonicalize(dir)?;
+        let contents = files::list_directory(&dir)?;
+        Ok(Self::listing_of(Listing::Directory(dir), &contents))
+    }
+
+    /// A read-only listing of the hits of a search for `query` under `root`,
+    /// one `path:line: text` line per hit.
+    pub fn search_results(root: &Path, query: &str, results: &[String]) -> Self
+    {
+        let listing = Listing::SearchResults {
+            root: root.to_path_buf(),
+            query: query.to_string()
+        };
+        Self::listing_of(listing, &results.join("\n"))
+    }
+
+    fn listing_of(listing: Listing, contents: &str) -> Self
+    {
+        Self {
+            read_only: true,
+            listing: Some(listing),
+            ..Self::from_text(contents)
+        }
+    }
+
+    /// The contents as they would be written to disk, without applying any
+    /// `SaveOptions`.
+    pub fn text(&self) -> String
+    {
+        self.join(&self.rows, self.trailing_newline)
+    }
+
+    fn join(&self, rows: &[Row], trailing_newline: bool) -> String
+    {
+        let mut text = String::new();
+        for (index, row) in rows.iter().enumerate()
+        {
+            text.push_str(std::str::from_utf8(row.as_bytes()).unwrap_or_default());
+            if index + 1 < rows.len() || trailing_newline
+            {
+                text.push_str(self.newline());
+            }
+        }
+        text
+    }
+
+    fn detect_crlf(contents: &str) -> bool
+    {
+        let lines = contents.matches('\n').count();
+        let crlf_lines = contents.matches("\r\n").count();
+        crlf_lines * 2 > lines
+    }
+
+    fn newline(&self) -> &'static str
+    {
+        if self.crlf { "\r\n" } else { "\n" }
+    }
+
+    // Uses a tab if the first indented line starts with one, otherwise the
+    // narrowest run of leading spaces found in the file.
+    fn detect_indent(contents: &str) -> String
+    {
+        let mut narrowest: Option<usize> = None;
+        for line in contents.lines()
         {
-            rows.push(Row::from(value));
+            if line.starts_with('\t') && narrowest.is_none()
+            {
+                return "\t".to_string();
+            }
+            let spaces = line.chars().take_while(|c| *c == ' ').count();
+            if spaces >= 2 && spaces < line.len()
+            {
+                narrowest = Some(narrowest.map_or(spaces, |n| n.min(spaces)));
+            }
         }
-        Ok(Self { rows, filename: Some(filename.to_string()), dirty: false })
+        narrowest.map_or(DEFAULT_INDENT.to_string(), |n| " ".repeat(n.min(8)))
     }
 
-    pub fn row(&self, index: usize) -> Option<&Row> 
+    /// The row at `index`, if there is one.
+    pub fn row(&self, index: usize) -> Option<&Row>
     {
         self.rows.get(index)
     }
 
+    /// Whether the document has no rows at all.
     pub fn is_empty(&self) -> bool
     {
         self.rows.is_empty()
     }
 
+    /// The file type, detected from the file name.
+    pub fn file_type(&self) -> &FileType
+    {
+        &self.file_type
+    }
+
+    /// The grapheme cluster at `at`, if there is one.
+    pub fn grapheme(&self, at: &Position) -> Option<&str>
+    {
+        self.rows.get(at.y)?.graphemes().nth(at.x)
+    }
+
+    /// The number of rows.
     pub fn len(&self) -> usize
     {
         self.rows.len()
     }
 
+    /// Inserts `c` at `at`, where `'\n'` splits the row. Positions past the
+    /// last row append a new one.
     pub fn insert(&mut self, at: &Position, c: char)
     {
-        if at.y > self.rows.len()
+        if self.read_only || at.y > self.rows.len()
         {
             return;
         }
 
-        self.dirty = true;
+        self.changed();
         if c == '\n'
         {
             self.insert_newline(at);
@@ -66,20 +346,23 @@ impl Document
         }
     }
 
+    /// Deletes the grapheme at `at`, joining the next row when `at` is at the
+    /// end of its row.
     pub fn delete(&mut self, at: &Position)
     {
         let len = self.rows.len();
-        if at.y >= len
+        if self.read_only || at.y >= len
         {
             return;
         }
 
-        self.dirty = true;
+        self.changed();
         if at.x == self.rows[at.y].len() && at.y + 1 < len
         {
             let next_row = self.rows.remove(at.y + 1);
             let row = &mut self.rows[at.y];
             row.append(&next_row);
+            self.line_shifts.push((at.y + 1, -1));
         }
         else
         {
@@ -88,6 +371,175 @@ impl Document
         }
     }
 
+    /// Prepends the detected indent unit to row `y` and returns how many
+    /// graphemes were added.
+    pub fn indent(&mut self, y: usize) -> usize
+    {
+        if self.read_only
+        {
+            return 0;
+        }
+        if let Some(row) = self.rows.get_mut(y)
+        {
+            row.indent(&self.indent_unit);
+            self.changed();
+            return self.indent_unit.chars().count();
+        }
+        0
+    }
+
+    /// Removes one level of indentation from row `y` and returns how many
+    /// graphemes were removed.
+    pub fn dedent(&mut self, y: usize) -> usize
+    {
+        let width = if self.indent_unit == "\t" {
+            TAB_WIDTH
+        }
+        else
+        {
+            self.indent_unit.len()
+        };
+        if let Some(row) = self.rows.get_mut(y).filter(|_| !self.read_only)
+        {
+            let removed = row.dedent(width);
+            if removed > 0
+            {
+                self.changed();
+            }
+            return removed;
+        }
+        0
+    }
+
+    /// Adds the file type's line-comment prefix to lines `first..=last`, aligned
+    /// at their minimum indentation, or removes it if every non-blank line is
+    /// already commented. Returns the edits made as (y, x, grapheme delta).
+    pub fn toggle_comment(&mut self, first: usize, last: usize) -> Option<Vec<(usize, usize, isize)>>
+    {
+        if self.read_only
+        {
+            return None;
+        }
+        let prefix = self.file_type.line_comment()?;
+        let last = last.min(self.rows.len().checked_sub(1)?);
+        let indentation = |row: &Row| row.graphemes().take_while(|g| g.trim().is_empty()).count();
+        let mut lines: Vec<usize> = (first..=last)
+            .filter(|&y| indentation(&self.rows[y]) < self.rows[y].len())
+            .collect();
+        if lines.is_empty()
+        {
+            lines = (first..=last).collect();
+        }
+        let column = lines.iter().map(|&y| indentation(&self.rows[y])).min()?;
+        let is_commented = lines.iter().all(|&y| {
+            let text: String = self.rows[y].graphemes().skip(indentation(&self.rows[y])).collect();
+            text.starts_with(prefix)
+        });
+
+        let mut edits = Vec::new();
+        for y in lines
+        {
+            let row = &mut self.rows[y];
+            if is_commented
+            {
+                let at = indentation(row);
+                let mut count = prefix.len();
+                if row.graphemes().nth(at + count) == Some(" ")
+                {
+                    count += 1;
+                }
+                row.remove(at, count);
+                edits.push((y, at, -(count as isize)));
+            }
+            else
+            {
+                row.insert_str(column, &format!("{} ", prefix));
+                edits.push((y, column, prefix.len() as isize + 1));
+            }
+        }
+        self.changed();
+        Some(edits)
+    }
+
+    /// Remembers the current contents so that the next edit (or group of
+    /// edits) can be reverted with a single `undo`.
+    pub fn checkpoint(&mut self, at: &Position)
+    {
+        self.push_snapshot(Snapshot {
+            rows: self.rows.clone(),
+            at: at.clone(),
+            version: self.version
+        });
+    }
+
+    fn push_snapshot(&mut self, snapshot: Snapshot)
+    {
+        self.typing_at = None;
+        if self.undo_stack.len() == UNDO_LIMIT
+        {
+            self.undo_stack.pop_front();
+        }
+        self.undo_stack.push_back(snapshot);
+    }
+
+    /// Like `checkpoint` before typing a character at `at`, except that a run
+    /// of characters typed one after the other shares a single checkpoint.
+    pub fn checkpoint_typing(&mut self, at: &Position)
+    {
+        if self.typing_at.as_ref() != Some(at)
+        {
+            self.checkpoint(at);
+        }
+        self.typing_at = Some(Position { x: at.x.saturating_add(1), y: at.y });
+    }
+
+    /// Restores the contents from the last checkpoint and returns the cursor
+    /// position recorded with it.
+    pub fn undo(&mut self) -> Option<Position>
+    {
+        if self.read_only
+        {
+            return None;
+        }
+        let snapshot = self.undo_stack.pop_back()?;
+        self.replace_rows(snapshot.rows);
+        self.version = snapshot.version;
+        self.typing_at = None;
+        Some(snapshot.at)
+    }
+
+    // Replaces all rows, remembering how the rows after the part that
+    // changed moved.
+    fn replace_rows(&mut self, rows: Vec<Row>)
+    {
+        let same = |(a, b): &(&Row, &Row)| a.as_bytes() == b.as_bytes();
+        let prefix = self.rows.iter().zip(&rows).take_while(same).count();
+        let suffix = self.rows.iter().rev().zip(rows.iter().rev())
+            .take(self.rows.len().min(rows.len()) - prefix)
+            .take_while(same)
+            .count();
+        let delta = rows.len() as isize - self.rows.len() as isize;
+        if delta != 0
+        {
+            self.line_shifts.push((self.rows.len() - suffix, delta));
+        }
+        self.rows = rows;
+    }
+
+    /// How rows moved since the last call, in the order of the edits: every
+    /// row from the first one given on moved by the given number of rows.
+    /// Other views of the document use this to stay on the same text.
+    pub fn take_line_shifts(&mut self) -> Vec<(usize, isize)>
+    {
+        std::mem::take(&mut self.line_shifts)
+    }
+
+    fn changed(&mut self)
+    {
+        self.versions += 1;
+        self.version = self.versions;
+    }
+
     fn insert_newline(&mut self, at: &Position)
     {
         if at.y > self.rows.len()
@@ -101,29 +553,346 @@ impl Document
         }
         let new_row = self.rows[at.y].split(at.x);
         self.rows.insert(at.y + 1, new_row);
+        self.line_shifts.push((at.y + 1, 1));
+    }
+
+    /// Writes the rows to disk after applying the enabled `options`, and
+    /// returns a description of each transform that changed something.
+    pub fn save(&mut self, options: &SaveOptions) -> Result<Vec<String>, Error>
+    {
+        if self.read_only
+        {
+            return Err(Error::new(io::ErrorKind::PermissionDenied, "read-only document"));
+        }
+        match self.filename.clone()
+        {
+            Some(filename) => self.save_to(&filename, options),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Saves to another file like `save` does, and binds the document to it
+    /// once it has been written, which also makes a read-only document
+    /// writable. The marker of the previous file is released; call `lock` to
+    /// mark the new one. Nothing changes if the file cannot be written.
+    pub fn save_as(&mut self, filename: &str, options: &SaveOptions) -> Result<Vec<String>, Error>
+    {
+        let report = self.save_to(filename, options)?;
+        self.unlock();
+        self.filename = Some(filename.to_string());
+        self.read_only = false;
+        Ok(report)
+    }
+
+    // The transforms are applied to a copy, which only replaces the rows once
+    // it has been written. Replacing them can be undone like any edit, which
+    // puts the cursor on the first row they changed.
+    fn save_to(&mut self, filename: &str, options: &SaveOptions) -> Result<Vec<String>, Error>
+    {
+        let mut rows = self.rows.clone();
+        let mut trailing_newline = self.trailing_newline;
+        let report = Self::apply_save_options(options, &mut rows, &mut trailing_newline);
+        let text = self.join(&rows, trailing_newline);
+        write_file(filename, &text)?;
+        self.trailing_newline = trailing_newline;
+        if !report.is_empty()
+        {
+            let same = rows.iter().zip(&self.rows).take_while(|(a, b)| a.as_bytes() == b.as_bytes()).count();
+            let at = Position { x: 0, y: same.min(self.rows.len().saturating_sub(1)) };
+            let version = self.version;
+            let previous = std::mem::replace(&mut self.rows, rows);
+            self.push_snapshot(Snapshot { rows: previous, at, version });
+            self.changed();
+        }
+        self.file_type = FileType::from(filename);
+        self.disk_state = Some(DiskState::new(&fs::metadata(filename)?, &text));
+        self.dismissed_change = None;
+        self.saved_version = self.version;
+        self.typing_at = None;
+        Ok(report)
+    }
+
+    /// Writes the contents to `filename` without binding the document to it.
+    pub fn write_copy(&self, filename: &str) -> Result<(), Error>
+    {
+        write_file(filename, &self.text())
+    }
+
+    /// Writes the text from `start` up to `end` to `filename`.
+    pub fn write_range(&self, start: &Position, end: &Position, filename: &str) -> Result<(), Error>
+    {
+        write_file(filename, &self.text_between(start, end))
+    }
+
+    /// The text from `start` up to, but not including, `end`.
+    pub fn text_between(&self, start: &Position, end: &Position) -> String
+    {
+        let mut text = String::new();
+        for (y, row) in self.rows.iter().enumerate().take(end.y.saturating_add(1)).skip(start.y)
+        {
+            let from = if y == start.y { start.x } else { 0 };
+            let to = if y == end.y { end.x } else { row.len() };
+            text.extend(row.graphemes().skip(from).take(to.saturating_sub(from)));
+            if y < end.y
+            {
+                text.push_str(self.newline());
+            }
+        }
+        text
     }
 
-    pub fn save(&mut self) -> Result<(), Error>
-    {   
-        if let Some(filename) = &self.filename
+    /// Marks the file as being edited here until the document is dropped or
+    /// bound to another file, and returns the running instance that already
+    /// edits it, if any. Read-only documents are never marked. A marker that
+    /// cannot be created is no reason to refuse editing, so that is ignored.
+    pub fn lock(&mut self) -> Option<&LockOwner>
+    {
+        self.unlock();
+        if let Some(filename) = self.filename.as_ref().filter(|_| !self.read_only)
         {
-            let mut file = fs::File::create(filename)?;
-            for row in &self.rows
+            match FileLock::acquire(filename)
             {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+                Ok(lock) => self.lock = Some(lock),
+                Err(owner) => self.lock_owner = owner,
             }
-            self.dirty = false;
         }
+        self.lock_owner.as_ref()
+    }
+
+    fn unlock(&mut self)
+    {
+        self.lock = None;
+        self.lock_owner = None;
+    }
+
+    /// Another running instance that was editing the file when it was last
+    /// locked.
+    pub fn lock_owner(&self) -> Option<&LockOwner>
+    {
+        self.lock_owner.as_ref()
+    }
+
+    /// Replaces the contents with the file on disk. The undo history is kept,
+    /// so a checkpoint taken before makes the reload undoable.
+    pub fn reload(&mut self) -> Result<(), Error>
+    {
+        let filename = self.filename.clone().ok_or_else(|| Error::new(io::ErrorKind::NotFound, "no file name"))?;
+        let reloaded = Self::read(&filename)?;
+        self.replace_rows(reloaded.rows);
+        self.trailing_newline = reloaded.trailing_newline;
+        self.crlf = reloaded.crlf;
+        self.indent_unit = reloaded.indent_unit;
+        self.read_only = self.read_only || reloaded.read_only;
+        self.disk_state = reloaded.disk_state;
+        self.dismissed_change = None;
+        self.changed();
+        self.saved_version = self.version;
+        self.typing_at = None;
         Ok(())
     }
 
+    /// How something else changed the file since it was last read or
+    /// written here, including a file appearing where there was none.
+    pub fn disk_change(&self) -> Option<DiskChange>
+    {
+        let filename = self.filename.as_ref()?;
+        match &self.disk_state
+        {
+            Some(_) if fs::metadata(filename).is_err_and(|error| error.kind() == io::ErrorKind::NotFound) => {
+                Some(DiskChange::Deleted)
+            },
+            Some(state) => state.read_if_changed(filename).map(DiskChange::Modified),
+            None => {
+                let metadata = fs::metadata(filename).ok()?;
+                Some(DiskChange::Modified(DiskState::new(&metadata, &fs::read_to_string(filename).ok()?)))
+            },
+        }
+    }
+
+    /// Like `disk_change`, but ignoring a change passed to `dismiss_change`.
+    pub fn new_disk_change(&self) -> Option<DiskChange>
+    {
+        self.disk_change().filter(|change| Some(change) != self.dismissed_change.as_ref())
+    }
+
+    /// Stops `new_disk_change` from reporting `change` again.
+    pub fn dismiss_change(&mut self, change: DiskChange)
+    {
+        self.dismissed_change = Some(change);
+    }
+
+    /// A unified diff from the file on disk to the contents of the document.
+    pub fn diff_with_disk(&self) -> Result<String, Error>
+    {
+        let filename = self.filename.as_deref().unwrap_or_default();
+        let on_disk = fs::read_to_string(filename)?;
+        let text = self.text();
+        let diff = TextDiff::from_lines(&on_disk, &text)
+            .unified_diff()
+            .header(&format!("{} (on disk)", filename), &format!("{} (buffer)", filename))
+            .to_string();
+        Ok(diff)
+    }
+
+    fn apply_save_options(options: &SaveOptions, rows: &mut Vec<Row>, trailing_newline: &mut bool) -> Vec<String>
+    {
+        let mut report = Vec::new();
+        if options.strip_trailing_whitespace
+        {
+            let stripped = rows.iter_mut().map(|row| row.trim_end()).filter(|changed| *changed).count();
+            if stripped > 0
+            {
+                report.push(format!("stripped trailing whitespace on {} lines", stripped));
+            }
+        }
+        if options.tabs_to_spaces
+        {
+            let expanded = rows.iter_mut().map(|row| row.expand_tabs(TAB_WIDTH)).filter(|changed| *changed).count();
+            if expanded > 0
+            {
+                report.push(format!("converted tabs on {} lines", expanded));
+            }
+        }
+        if options.ensure_final_newline
+        {
+            let mut removed = 0;
+            while rows.last().is_some_and(Row::is_empty)
+            {
+                rows.pop();
+                removed += 1;
+            }
+            if removed > 0
+            {
+                report.push(format!("removed {} blank lines at end of file", removed));
+            }
+            if !*trailing_newline && !rows.is_empty()
+            {
+                report.push("added final newline".to_string());
+            }
+            *trailing_newline = true;
+        }
+        report
+    }
+
+    /// `CRLF` or `LF`, whichever all lines are written with.
+    pub fn line_ending(&self) -> &'static str
+    {
+        if self.crlf { "CRLF" } else { "LF" }
+    }
+
+    /// What the document lists, if it is a generated listing.
+    pub fn listing(&self) -> Option<&Listing>
+    {
+        self.listing.as_ref()
+    }
+
+    /// The directory that relative paths typed while editing this document
+    /// are resolved against. Empty for the working directory.
+    pub fn directory(&self) -> PathBuf
+    {
+        match (&self.listing, &self.filename)
+        {
+            (Some(Listing::Directory(dir) | Listing::SearchResults { root: dir, .. }), _) => dir.clone(),
+            (None, Some(filename)) => Path::new(filename).parent().map(Path::to_path_buf).unwrap_or_default(),
+            (None, None) => PathBuf::new(),
+        }
+    }
+
+    /// The file name, or what the buffer shows if it is not backed by a file.
+    pub fn display_name(&self) -> String
+    {
+        match (&self.listing, &self.filename)
+        {
+            (Some(Listing::Directory(dir)), _) => format!("{}/", dir.display()),
+            (Some(Listing::SearchResults { query, .. }), _) => format!("[Search: {}]", query),
+            (None, Some(filename)) => filename.clone(),
+            (None, None) => "[No Name]".to_string(),
+        }
+    }
+
+    /// Whether the contents differ from what was last read or saved.
     pub fn is_dirty(&self) -> bool
     {
-        self.dirty
+        self.version != self.saved_version
+    }
+
+    /// Whether the editor should refuse to change or overwrite the file,
+    /// because it was opened read-only or is not writable.
+    pub fn is_read_only(&self) -> bool
+    {
+        self.read_only
+    }
+
+    /// Making a document read-only releases its marker.
+    pub fn set_read_only(&mut self, read_only: bool)
+    {
+        self.read_only = read_only;
+        if read_only
+        {
+            self.unlock();
+        }
+    }
+
+    /// Finds the bracket matching the one at `at` within `rows`, skipping over
+    /// nested pairs.
+    pub fn matching_bracket(&self, at: &Position, rows: Range<usize>) -> Option<Position>
+    {
+        let current = self.grapheme(at)?;
+        let (open, close) = *BRACKET_PAIRS
+            .iter()
+            .find(|(open, close)| current == *open || current == *close)?;
+        let mut depth: usize = 0;
+        if current == open
+        {
+            for (y, row) in self.rows.iter().enumerate().take(rows.end).skip(at.y)
+            {
+                let skip = if y == at.y { at.x } else { 0 };
+                for (x, grapheme) in row.graphemes().enumerate().skip(skip)
+                {
+                    if grapheme == open
+                    {
+                        depth += 1;
+                    }
+                    else if grapheme == close
+                    {
+                        depth -= 1;
+                        if depth == 0
+                        {
+                            return Some(Position { x, y });
+                        }
+                    }
+                }
+            }
+        }
+        else
+        {
+            for y in (rows.start..=at.y).rev()
+            {
+                let row = &self.rows[y];
+                let skip = if y == at.y { row.len().saturating_sub(at.x + 1) } else { 0 };
+                for (x, grapheme) in row.graphemes().rev().enumerate().skip(skip)
+                {
+                    if grapheme == close
+                    {
+                        depth += 1;
+                    }
+                    else if grapheme == open
+                    {
+                        depth -= 1;
+                        if depth == 0
+                        {
+                            return Some(Position { x: row.len() - 1 - x, y });
+                        }
+                    }
+                }
+            }
+        }
+        None
     }
 
-    pub fn find(&self, query: & str, at: &Position, direction: SearchDirection) -> Option<Position> 
+    /// Finds the next occurrence of `query` starting at `at`, searching
+    /// towards the end or the start of the document.
+    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position>
     {
         if at.y >= self.rows.len()
         {
@@ -147,7 +916,7 @@ impl Document
         {
             if let Some(row) = self.rows.get(position.y)
             {
-                if let Some(x) = row.find(&query, position.x, direction)
+                if let Some(x) = row.find(query, position.x, direction)
                 {
                     position.x = x;
                     return Some(position);
@@ -170,4 +939,452 @@ impl Document
         }
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use proptest::prelude::*;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    fn document(lines: &[&str]) -> Document
+    {
+        let mut document = Document::default();
+        for (y, line) in lines.iter().enumerate()
+        {
+            if y > 0
+            {
+                let x = document.row(y - 1).map_or(0, Row::len);
+                document.insert(&Position { x, y: y - 1 }, '\n');
+            }
+            for (x, c) in line.chars().enumerate()
+            {
+                document.insert(&Position { x, y }, c);
+            }
+        }
+        document
+    }
+
+    fn lines(document: &Document) -> Vec<String>
+    {
+        (0..document.len())
+            .map(|y| String::from_utf8(document.row(y).unwrap().as_bytes().to_vec()).unwrap())
+            .collect()
+    }
+
+    fn at(x: usize, y: usize) -> Position
+    {
+        Position { x, y }
+    }
+
+    #[test]
+    fn inserts_newlines_at_row_end_and_past_last_row()
+    {
+        let mut doc = document(&["héllo"]);
+        doc.insert(&at(2, 0), '\n');
+        assert_eq!(lines(&doc), ["hé", "llo"]);
+        doc.insert(&at(0, 2), '\n');
+        assert_eq!(lines(&doc), ["hé", "llo", ""]);
+        doc.insert(&at(0, 9), 'x');
+        assert_eq!(doc.len(), 3);
+        doc.insert(&at(0, 3), 'x');
+        assert_eq!(lines(&doc), ["hé", "llo", "", "x"]);
+        assert!(doc.is_dirty());
+    }
+
+    #[test]
+    fn deleting_at_row_end_joins_next_row()
+    {
+        let mut doc = document(&["ab", "çd", "e"]);
+        doc.delete(&at(2, 0));
+        assert_eq!(lines(&doc), ["abçd", "e"]);
+        doc.delete(&at(1, 1));
+        assert_eq!(lines(&doc), ["abçd", "e"]);
+        doc.delete(&at(0, 5));
+        doc.delete(&at(2, 0));
+        assert_eq!(lines(&doc), ["abd", "e"]);
+    }
+
+    #[test]
+    fn finds_forward_across_rows()
+    {
+        let doc = document(&["one two", "three two", "four"]);
+        let found = doc.find("two", &at(5, 0), SearchDirection::Forward).unwrap();
+        assert_eq!((found.x, found.y), (6, 1));
+        assert!(doc.find("two", &at(7, 1), SearchDirection::Forward).is_none());
+        assert!(doc.find("two", &at(0, 3), SearchDirection::Forward).is_none());
+    }
+
+    #[test]
+    fn finds_backward_across_rows()
+    {
+        let doc = document(&["añ two", "x", "three"]);
+        let found = doc.find("two", &at(0, 2), SearchDirection::Backward).unwrap();
+        assert_eq!((found.x, found.y), (3, 0));
+        let found = doc.find("th", &at(5, 2), SearchDirection::Backward).unwrap();
+        assert_eq!((found.x, found.y), (0, 2));
+        assert!(doc.find("three", &at(0, 2), SearchDirection::Backward).is_none());
+    }
+
+    #[test]
+    fn undo_reverts_to_checkpoint()
+    {
+        let mut doc = document(&["a", "b"]);
+        doc.checkpoint(&at(1, 0));
+        doc.indent(0);
+        doc.indent(1);
+        assert_eq!(lines(&doc), ["    a", "    b"]);
+        let position = doc.undo().unwrap();
+        assert_eq!(lines(&doc), ["a", "b"]);
+        assert_eq!((position.x, position.y), (1, 0));
+        assert!(doc.undo().is_none());
+    }
+
+    #[test]
+    fn read_only_documents_ignore_edits()
+    {
+        let mut doc = document(&["a"]);
+        doc.checkpoint(&at(0, 0));
+        doc.set_read_only(true);
+        doc.insert(&at(0, 0), 'x');
+        doc.delete(&at(0, 0));
+        assert_eq!(doc.indent(0), 0);
+        assert!(doc.undo().is_none());
+        assert_eq!(lines(&doc), ["a"]);
+        assert_eq!(doc.save(&SaveOptions::default()).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn reports_how_rows_moved()
+    {
+        let mut doc = document(&["a", "bc", "d"]);
+        doc.take_line_shifts();
+        doc.checkpoint(&at(1, 1));
+        doc.insert(&at(1, 1), '\n');
+        doc.delete(&at(1, 0));
+        assert_eq!(doc.take_line_shifts(), [(2, 1), (1, -1)]);
+        assert_eq!(lines(&doc), ["ab", "c", "d"]);
+        doc.undo();
+        assert!(doc.take_line_shifts().is_empty());
+
+        doc.checkpoint(&at(0, 1));
+        doc.insert(&at(0, 1), '\n');
+        doc.take_line_shifts();
+        doc.undo();
+        assert_eq!(doc.take_line_shifts(), [(2, -1)]);
+    }
+
+    #[test]
+    fn typing_runs_share_a_checkpoint()
+    {
+        let mut doc = Document::default();
+        for (x, c) in "ab".chars().enumerate()
+        {
+            doc.checkpoint_typing(&at(x, 0));
+            doc.insert(&at(x, 0), c);
+        }
+        doc.checkpoint_typing(&at(0, 0));
+        doc.insert(&at(0, 0), '>');
+        assert_eq!(lines(&doc), [">ab"]);
+        doc.undo();
+        assert_eq!(lines(&doc), ["ab"]);
+        doc.undo();
+        assert!(doc.is_empty());
+        assert!(doc.undo().is_none());
+    }
+
+    #[test]
+    fn undoing_back_to_the_saved_contents_is_not_dirty()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "a\n").unwrap();
+        let mut doc = Document::open(path.to_str().unwrap()).unwrap();
+        doc.checkpoint(&at(0, 0));
+        doc.insert(&at(0, 0), 'x');
+        assert!(doc.is_dirty());
+        doc.undo();
+        assert!(!doc.is_dirty());
+
+        doc.checkpoint(&at(0, 0));
+        doc.insert(&at(0, 0), 'y');
+        doc.save(&SaveOptions::default()).unwrap();
+        doc.undo();
+        assert!(doc.is_dirty());
+    }
+
+    #[test]
+    fn detects_indent_unit()
+    {
+        assert_eq!(Document::detect_indent("a\n\tb\n"), "\t");
+        assert_eq!(Document::detect_indent("a\n    b\n  c\n"), "  ");
+        assert_eq!(Document::detect_indent("a\n"), DEFAULT_INDENT);
+    }
+
+    #[test]
+    fn round_trips_text_with_and_without_final_newline()
+    {
+        for text in ["", "a\n", "a\n\tb", "a\n\n"]
+        {
+            let document = Document::from_text(text);
+            assert_eq!(document.text(), text);
+            assert!(!document.is_dirty());
+        }
+        assert_eq!(Document::from_text("x\n  y\n").indent_unit, "  ");
+        let document = Document::from_text("dos\r\nlines\r\n");
+        assert_eq!((document.len(), document.text()), (2, "dos\r\nlines\r\n".to_string()));
+        assert_eq!(document.line_ending(), "CRLF");
+        assert_eq!(Document::from_text("a\r\n").row(0).unwrap().len(), 1);
+        let mixed = Document::from_text("a\nb\r\nc\r\n");
+        assert_eq!((mixed.line_ending(), mixed.text()), ("CRLF", "a\r\nb\r\nc\r\n".to_string()));
+        let mixed = Document::from_text("a\r\nb\n");
+        assert_eq!((mixed.line_ending(), mixed.text()), ("LF", "a\nb\n".to_string()));
+        assert_eq!(mixed.text_between(&at(0, 0), &at(1, 1)), "a\nb");
+        assert_eq!(document.text_between(&at(1, 0), &at(2, 1)), "os\r\nli");
+        let document = Document::from_reader("piped\ntext".as_bytes()).unwrap();
+        assert_eq!((document.len(), document.text()), (2, "piped\ntext".to_string()));
+        assert!(document.filename.is_none());
+    }
+
+    #[test]
+    fn toggles_comments_at_minimum_indentation()
+    {
+        let mut doc = document(&["  if x:", "", "    y()"]);
+        doc.file_type = FileType::from("script.py");
+        doc.toggle_comment(0, 2);
+        assert_eq!(lines(&doc), ["  # if x:", "", "  #   y()"]);
+        doc.toggle_comment(0, 2);
+        assert_eq!(lines(&doc), ["  if x:", "", "    y()"]);
+        doc.file_type = FileType::default();
+        assert!(doc.toggle_comment(0, 2).is_none());
+    }
+
+    #[test]
+    fn matches_nested_brackets_across_rows()
+    {
+        let doc = document(&["f(a[1], {", "  (b)", "})"]);
+        let matching = |x, y| doc.matching_bracket(&at(x, y), 0..doc.len()).map(|p| (p.x, p.y));
+        assert_eq!(matching(1, 0), Some((1, 2)));
+        assert_eq!(matching(1, 2), Some((1, 0)));
+        assert_eq!(matching(3, 0), Some((5, 0)));
+        assert_eq!(matching(8, 0), Some((0, 2)));
+        assert_eq!(matching(2, 1), Some((4, 1)));
+        assert_eq!(matching(0, 0), None);
+        assert_eq!(doc.matching_bracket(&at(1, 0), 0..2), None);
+        assert_eq!(doc.matching_bracket(&at(1, 2), 1..3), None);
+    }
+
+    #[test]
+    fn saves_with_transforms()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "a  \n\tb\n\n\n").unwrap();
+        let mut doc = Document::open(path.to_str().unwrap()).unwrap();
+        let options = SaveOptions {
+            strip_trailing_whitespace: true,
+            ensure_final_newline: true,
+            tabs_to_spaces: true
+        };
+        let report = doc.save(&options).unwrap();
+        assert_eq!(report.len(), 3);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n    b\n");
+        assert!(!doc.is_dirty());
+        assert_eq!(doc.undo(), Some(at(0, 0)));
+        assert_eq!(lines(&doc), ["a  ", "\tb", "", ""]);
+        assert!(doc.is_dirty());
+
+        let mut read_only = Document::open(path.to_str().unwrap()).unwrap();
+        read_only.set_read_only(true);
+        fs::write(&path, "a   \n\n\n").unwrap();
+        read_only.reload().unwrap();
+        assert!(read_only.save_as(path.join("x.txt").to_str().unwrap(), &options).is_err());
+        assert_eq!(read_only.text(), "a   \n\n\n");
+        assert!(read_only.is_read_only() && !read_only.is_dirty());
+    }
+
+    #[test]
+    fn saves_verbatim_without_transforms()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("raw.txt");
+        fs::write(&path, "a \n\nno newline").unwrap();
+        let mut doc = Document::open(path.to_str().unwrap()).unwrap();
+        let options = SaveOptions {
+            strip_trailing_whitespace: false,
+            ensure_final_newline: false,
+            tabs_to_spaces: false
+        };
+        assert!(doc.save(&options).unwrap().is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a \n\nno newline");
+    }
+
+    #[test]
+    fn detects_and_reloads_external_changes()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.txt");
+        fs::write(&path, "one\n").unwrap();
+        let mut doc = Document::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(doc.disk_change(), None);
+
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let change = doc.disk_change().unwrap();
+        doc.insert(&at(0, 0), '>');
+        assert!(doc.diff_with_disk().unwrap().contains("-two\n"));
+        doc.dismiss_change(change);
+        assert_eq!(doc.new_disk_change(), None);
+        assert!(doc.disk_change().is_some());
+
+        doc.checkpoint(&at(0, 0));
+        doc.reload().unwrap();
+        assert_eq!(lines(&doc), ["one", "two"]);
+        assert!(!doc.is_dirty() && doc.disk_change().is_none());
+        assert_eq!(doc.undo(), Some(at(0, 0)));
+        assert_eq!(lines(&doc), [">one"]);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(doc.disk_change(), Some(DiskChange::Deleted));
+        doc.dismiss_change(DiskChange::Deleted);
+        assert_eq!(doc.new_disk_change(), None);
+        fs::write(&path, "back\n").unwrap();
+        assert!(matches!(doc.new_disk_change(), Some(DiskChange::Modified(_))));
+    }
+
+    #[test]
+    fn writes_copies_and_ranges_into_new_directories()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let doc = document(&["héllo", "wörld", "!"]);
+        assert_eq!(doc.text_between(&at(1, 0), &at(1, 2)), "éllo\nwörld\n!");
+        assert_eq!(doc.text_between(&at(3, 1), &at(5, 1)), "ld");
+        assert_eq!(Document::from_text("").text_between(&at(0, 0), &at(0, 0)), "");
+
+        let copy = dir.path().join("a/b/copy.txt");
+        doc.write_copy(copy.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "héllo\nwörld\n!\n");
+        let part = dir.path().join("c/part.txt");
+        doc.write_range(&at(0, 1), &at(1, 1), part.to_str().unwrap()).unwrap();
+        assert_eq!(fs::read_to_string(&part).unwrap(), "w");
+        assert!(doc.filename.is_none());
+    }
+
+    #[test]
+    fn locks_files_until_dropped()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.txt");
+        let marker = dir.path().join(".shared.txt.hecto-lock");
+        fs::write(&path, "one\n").unwrap();
+        let mut first = Document::open(path.to_str().unwrap()).unwrap();
+        assert!(!marker.exists());
+        assert!(first.lock().is_none() && marker.exists());
+        let mut second = Document::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(second.lock().map(|owner| owner.pid), Some(std::process::id()));
+        drop(second);
+        assert!(marker.exists());
+        drop(first);
+        assert!(!marker.exists());
+
+        let mut read_only = Document::open(path.to_str().unwrap()).unwrap();
+        read_only.set_read_only(true);
+        assert!(read_only.lock().is_none() && !marker.exists());
+        let mut moved = Document::open(path.to_str().unwrap()).unwrap();
+        moved.lock();
+        let other = dir.path().join("other.txt");
+        assert!(moved.save_as(path.join("other.txt").to_str().unwrap(), &SaveOptions::default()).is_err());
+        assert!(marker.exists() && moved.filename.as_deref() == path.to_str());
+        moved.save_as(other.to_str().unwrap(), &SaveOptions::default()).unwrap();
+        assert!(!marker.exists() && moved.filename.as_deref() == other.to_str());
+    }
+
+    #[derive(Clone, Debug)]
+    enum Edit
+    {
+        Insert(usize, usize, char),
+        Newline(usize, usize),
+        Delete(usize, usize)
+    }
+
+    fn edit_strategy() -> impl Strategy<Value = Edit>
+    {
+        // Combining marks and joiners change how neighbouring characters are
+        // grouped into graphemes, which is where length bookkeeping breaks.
+        let chars = prop::sample::select(vec!['a', ' ', '\t', 'é', 'e', '\u{301}', '\u{200d}', '👍', '\u{1f3fd}', '🇺', '🇸']);
+        prop_oneof![
+            (0..8usize, 0..5usize, chars).prop_map(|(x, y, c)| Edit::Insert(x, y, c)),
+            (0..8usize, 0..5usize).prop_map(|(x, y)| Edit::Newline(x, y)),
+            (0..8usize, 0..5usize).prop_map(|(x, y)| Edit::Delete(x, y)),
+        ]
+    }
+
+    fn byte_index(line: &str, x: usize) -> usize
+    {
+        line.grapheme_indices(true).nth(x).map_or(line.len(), |(index, _)| index)
+    }
+
+    // The behaviour `Document` should have, written against plain strings.
+    fn apply_to_model(model: &mut Vec<String>, edit: &Edit)
+    {
+        match *edit
+        {
+            Edit::Insert(x, y, c) if y < model.len() => {
+                let index = byte_index(&model[y], x);
+                model[y].insert(index, c);
+            },
+            Edit::Insert(_, y, c) if y == model.len() => model.push(c.to_string()),
+            Edit::Newline(x, y) if y < model.len() => {
+                let index = byte_index(&model[y], x);
+                let tail = model[y].split_off(index);
+                model.insert(y + 1, tail);
+            },
+            Edit::Newline(_, y) if y == model.len() => model.push(String::new()),
+            Edit::Delete(x, y) if y < model.len() => {
+                let graphemes = model[y].graphemes(true).count();
+                if x == graphemes && y + 1 < model.len()
+                {
+                    let next = model.remove(y + 1);
+                    model[y].push_str(&next);
+                }
+                else if x < graphemes
+                {
+                    let start = byte_index(&model[y], x);
+                    let end = byte_index(&model[y], x + 1);
+                    model[y].replace_range(start..end, "");
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn apply_to_document(document: &mut Document, edit: &Edit)
+    {
+        match *edit
+        {
+            Edit::Insert(x, y, c) => document.insert(&at(x, y), c),
+            Edit::Newline(x, y) => document.insert(&at(x, y), '\n'),
+            Edit::Delete(x, y) => document.delete(&at(x, y)),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn edits_match_string_model(edits in prop::collection::vec(edit_strategy(), 0..60))
+        {
+            let mut document = Document::default();
+            let mut model: Vec<String> = Vec::new();
+            for edit in &edits
+            {
+                apply_to_document(&mut document, edit);
+                apply_to_model(&mut model, edit);
+                prop_assert_eq!(&lines(&document), &model);
+                for y in 0..document.len()
+                {
+                    let row = document.row(y).unwrap();
+                    prop_assert_eq!(row.len(), row.graphemes().count());
+                }
+            }
+        }
+    }
+}
@@ -1,28 +1,60 @@
+use crate::document::BRACKET_PAIRS;
+use crate::Args;
+use crate::cells;
+use crate::Config;
+use crate::DiskChange;
 use crate::Document;
+use crate::Event;
+use crate::files;
+use crate::FileType;
+use crate::layout::{Layout, Rect, Side, SplitDirection};
+use crate::Listing;
+use crate::status_line;
 use crate::Row;
 use crate::Terminal;
-use std::env;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::time::Instant;
 use termion::color;
-use termion::event::Key;
+use termion::event::{Key, MouseButton, MouseEvent};
+use termion::style;
+use unicode_segmentation::UnicodeSegmentation;
 
 const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
 const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+const MATCH_BG_COLOR: color::Rgb = color::Rgb(70, 90, 150);
+const QUOTES: [&str; 3] = ["\"", "'", "`"];
+/// The version of hecto, as shown by `--version`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 const QUIT_TIMES: u8 = 3;
+const WHEEL_LINES: usize = 3;
+const FINDER_FILE_LIMIT: usize = 50_000;
+const FINDER_LINES: usize = 10;
+const SEARCH_RESULT_LIMIT: usize = 10_000;
+const RESIZE_STEP: isize = 5;
+const TAB_NAME_WIDTH: usize = 24;
 
+/// Which way `Document::find` searches from its starting position.
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection
 {
+    /// Towards the end of the document.
     Forward,
+    /// Towards the start of the document.
     Backward
 }
 
-#[derive(Default, Clone)]
+/// A location in a document as (grapheme index, row), or a cell on the
+/// screen.
+#[derive(Default, Clone, PartialEq, Debug)]
 pub struct Position
 {
+    /// The grapheme index, or the screen column.
     pub x: usize,
+    /// The row, or the screen row.
     pub y: usize
 }
 
@@ -43,6 +75,26 @@ impl StatusMessage
     }
 }
 
+// An open document that is not currently shown, with the view it was left in.
+struct Buffer
+{
+    document: Document,
+    cursor_position: Position,
+    offset: Position
+}
+
+// A view into one of the buffers. The focused window keeps its cursor and
+// offset in the editor while it has the focus.
+#[derive(Clone, Default)]
+struct Window
+{
+    buffer: usize,
+    cursor_position: Position,
+    offset: Position
+}
+
+/// The interactive editor: open documents shown in split windows on a
+/// `Terminal`, driven by its input events.
 pub struct Editor
 {
     should_quit: bool,
@@ -50,214 +102,1416 @@ pub struct Editor
     document: Document,
     cursor_position: Position,
     offset: Position,
+    // The other buffers in order, with the shown one taken out at
+    // `buffer_index`.
+    buffers: Vec<Buffer>,
+    buffer_index: usize,
+    layout: Layout,
+    windows: Vec<Window>,
+    window_index: usize,
     status_message: StatusMessage,
     quit_times: u8,
+    selection_anchor: Option<Position>,
+    auto_pair: bool,
+    show_whitespace: bool,
+    // Choices drawn over the bottom of the text area while a prompt offers
+    // them.
+    overlay: Vec<String>,
+    // The git branch of each document directory, read again on focus and
+    // when idle.
+    branches: HashMap<PathBuf, Option<String>>,
+    // Where project-wide commands look for files: the working directory the
+    // editor was started in.
+    project_root: PathBuf,
+    config: Config,
 }
 
 impl Editor
 {
-    pub fn default() -> Self
+    /// An editor on the real terminal with the files and settings given on
+    /// the command line. Everything is read before the terminal is switched
+    /// to raw mode, so that errors can still be printed normally.
+    pub fn open(args: &Args) -> Result<Self, String>
     {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status = String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(&file_name);
-            if let Ok(doc) = doc
+        let mut initial_status = String::from("HELP: Ctrl-O = open | Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
+        let config = match &args.config
+        {
+            Some(path) => Config::load(path)?,
+            None => match Config::default_path().filter(|path| path.exists())
             {
-                doc
-            }
-            else
+                Some(path) => Config::load(&path).unwrap_or_else(|error| {
+                    initial_status = format!("ERR: Could not load config: {}", error);
+                    Config::default()
+                }),
+                None => Config::default()
+            },
+        };
+        let mut documents = Vec::new();
+        let mut read_stdin = false;
+        for file_name in &args.files
+        {
+            if file_name == "-"
             {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+                if read_stdin
+                {
+                    return Err("-: standard input can only be read once".to_string());
+                }
+                read_stdin = true;
+                let document = Document::from_reader(std::io::stdin())
+                    .map_err(|error| format!("-: {}", error))?;
+                documents.push(document);
+                continue;
             }
+            documents.push(match Document::open(file_name)
+            {
+                Ok(document) => document,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => Document::new_file(file_name),
+                Err(error) => return Err(format!("{}: {}", file_name, error)),
+            });
         }
-        else 
+        if args.readonly
         {
-            Document::default()
-        };
+            documents.iter_mut().for_each(|document| document.set_read_only(true));
+        }
+        let warnings: Vec<String> = documents.iter_mut().filter_map(lock_document).collect();
+        if let Some(warning) = warnings.into_iter().next()
+        {
+            initial_status = warning;
+        }
+        let mut documents = documents.into_iter();
+        let terminal = Terminal::default().map_err(|error| format!("could not initialize terminal: {}", error))?;
+        let mut editor = Self::new(terminal, documents.next().unwrap_or_default(), config);
+        for document in documents
+        {
+            editor.add_buffer(document);
+        }
+        if let Some(line) = args.line
+        {
+            editor.go_to_line(line);
+        }
+        editor.status_message = StatusMessage::from(initial_status);
+        Ok(editor)
+    }
+
+    /// An editor showing `document` on `terminal`.
+    pub fn new(terminal: Terminal, document: Document, config: Config) -> Self
+    {
         Self { 
             should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
-            document: document,
+            terminal,
+            document,
             cursor_position: Position::default(),
             offset: Position::default(),
-            status_message: StatusMessage::from(initial_status),
+            buffers: Vec::new(),
+            buffer_index: 0,
+            layout: Layout::default(),
+            windows: vec![Window::default()],
+            window_index: 0,
+            status_message: StatusMessage::from(String::new()),
             quit_times: QUIT_TIMES,
+            selection_anchor: None,
+            auto_pair: true,
+            show_whitespace: false,
+            overlay: Vec::new(),
+            branches: HashMap::new(),
+            project_root: std::env::current_dir().unwrap_or_default(),
+            config,
         }
     }
 
-    pub fn run(&mut self)
+    /// Draws and handles input until the user quits or input runs out.
+    pub fn run(&mut self) -> Result<(), std::io::Error>
     {
         loop
         {
-            if let Err(error) = self.refresh_screen()
+            if self.should_quit
             {
-                Self::die(error);
+                return Ok(());
             }
-            if self.should_quit 
+            self.refresh_screen()?;
+            self.process_keypress()?;
+        }
+    }
+
+    /// Opens `document` in a new buffer after all the others, without
+    /// showing it.
+    pub fn add_buffer(&mut self, document: Document)
+    {
+        self.buffers.push(Buffer {
+            document,
+            cursor_position: Position::default(),
+            offset: Position::default()
+        });
+    }
+
+    /// The number of open buffers, including the shown one.
+    pub fn buffer_count(&self) -> usize
+    {
+        self.buffers.len() + 1
+    }
+
+    // Shows the buffer at `index`, counting the shown one, and puts the
+    // current one back in its place.
+    fn switch_buffer(&mut self, index: usize)
+    {
+        if index == self.buffer_index || index >= self.buffer_count()
+        {
+            return;
+        }
+        let current = Buffer {
+            document: std::mem::take(&mut self.document),
+            cursor_position: std::mem::take(&mut self.cursor_position),
+            offset: std::mem::take(&mut self.offset)
+        };
+        self.buffers.insert(self.buffer_index, current);
+        let next = self.buffers.remove(index);
+        self.document = next.document;
+        self.cursor_position = next.cursor_position;
+        self.offset = next.offset;
+        self.buffer_index = index;
+        self.selection_anchor = None;
+    }
+
+    fn cycle_buffer(&mut self, forward: bool)
+    {
+        let count = self.buffer_count();
+        let index = if forward {
+            (self.buffer_index + 1) % count
+        }
+        else
+        {
+            (self.buffer_index + count - 1) % count
+        };
+        self.switch_buffer(index);
+    }
+
+    // The buffer, counting the shown one, editing the file at `path`.
+    fn find_buffer(&self, path: &Path) -> Option<usize>
+    {
+        let path = fs::canonicalize(path).ok()?;
+        let edits_path = |document: &Document| {
+            document.filename.as_ref().and_then(|name| fs::canonicalize(name).ok()).as_ref() == Some(&path)
+        };
+        if edits_path(&self.document)
+        {
+            return Some(self.buffer_index);
+        }
+        let index = self.buffers.iter().position(|buffer| edits_path(&buffer.document))?;
+        Some(if index < self.buffer_index { index } else { index + 1 })
+    }
+
+    fn is_any_dirty(&self) -> bool
+    {
+        self.document.is_dirty() || self.buffers.iter().any(|buffer| buffer.document.is_dirty())
+    }
+
+    // The document of the buffer at `index`, counting the shown one.
+    fn buffer_document(&self, index: usize) -> &Document
+    {
+        match index.cmp(&self.buffer_index)
+        {
+            std::cmp::Ordering::Equal => &self.document,
+            std::cmp::Ordering::Less => &self.buffers[index].document,
+            std::cmp::Ordering::Greater => &self.buffers[index - 1].document,
+        }
+    }
+
+    // The part of the screen shared by the windows, between the tab bar and
+    // the status bar.
+    fn text_area(&self) -> Rect
+    {
+        let size = self.terminal.size();
+        Rect {
+            x: 0,
+            y: self.terminal.top_lines() as usize,
+            width: size.width as usize,
+            height: size.height as usize
+        }
+    }
+
+    // The part of the text area showing the focused window.
+    fn window_area(&self) -> Rect
+    {
+        let area = self.text_area();
+        self.layout
+            .areas(area)
+            .into_iter()
+            .find(|(window, _)| *window == self.window_index)
+            .map_or(area, |(_, area)| area)
+    }
+
+    fn window_at(&self, x: usize, y: usize) -> Option<usize>
+    {
+        self.layout
+            .areas(self.text_area())
+            .into_iter()
+            .find(|(_, area)| area.contains(x, y))
+            .map(|(window, _)| window)
+    }
+
+    // Reads a window command after Ctrl-W, like in vi.
+    fn window_command(&mut self) -> Result<(), std::io::Error>
+    {
+        self.status_message = StatusMessage::from(
+            "Window: (s)plit, (v)ertical split, (c)lose, (w) next, arrows move, + - < > resize".to_string()
+        );
+        self.refresh_screen()?;
+        let key = self.read_key()?;
+        self.status_message = StatusMessage::from(String::new());
+        match key
+        {
+            Key::Char('s') => self.split_window(SplitDirection::Horizontal),
+            Key::Char('v') => self.split_window(SplitDirection::Vertical),
+            Key::Char('c' | 'q') => self.close_window(),
+            Key::Char('w') => self.focus_window((self.window_index + 1) % self.windows.len()),
+            Key::Left | Key::Char('h') => self.focus_side(Side::Left),
+            Key::Right | Key::Char('l') => self.focus_side(Side::Right),
+            Key::Up | Key::Char('k') => self.focus_side(Side::Above),
+            Key::Down | Key::Char('j') => self.focus_side(Side::Below),
+            Key::Char('+') => self.resize_window(SplitDirection::Horizontal, RESIZE_STEP),
+            Key::Char('-') => self.resize_window(SplitDirection::Horizontal, -RESIZE_STEP),
+            Key::Char('>') => self.resize_window(SplitDirection::Vertical, RESIZE_STEP),
+            Key::Char('<') => self.resize_window(SplitDirection::Vertical, -RESIZE_STEP),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // Shows the current buffer in a new window taking half of the focused
+    // one, and focuses it.
+    fn split_window(&mut self, direction: SplitDirection)
+    {
+        let window = self.windows.len();
+        self.windows.push(Window {
+            buffer: self.buffer_index,
+            cursor_position: self.cursor_position.clone(),
+            offset: self.offset.clone()
+        });
+        self.layout.split(self.window_index, window, direction);
+        self.focus_window(window);
+    }
+
+    fn close_window(&mut self)
+    {
+        let Some(focus) = self.layout.close(self.window_index) else {
+            self.status_message = StatusMessage::from("Cannot close the last window.".to_string());
+            return;
+        };
+        self.windows.remove(self.window_index);
+        self.window_index = focus;
+        self.show_window();
+    }
+
+    fn focus_side(&mut self, side: Side)
+    {
+        if let Some(window) = self.layout.neighbor(self.text_area(), self.window_index, side)
+        {
+            self.focus_window(window);
+        }
+    }
+
+    fn resize_window(&mut self, direction: SplitDirection, delta: isize)
+    {
+        if !self.layout.resize(self.window_index, direction, delta)
+        {
+            self.status_message = StatusMessage::from("No split to resize that way.".to_string());
+        }
+    }
+
+    fn focus_window(&mut self, window: usize)
+    {
+        if window == self.window_index
+        {
+            return;
+        }
+        self.windows[self.window_index] = Window {
+            buffer: self.buffer_index,
+            cursor_position: self.cursor_position.clone(),
+            offset: self.offset.clone()
+        };
+        self.window_index = window;
+        self.show_window();
+    }
+
+    // Takes the buffer and view of the focused window into the editor. The
+    // cursor is clamped, as the buffer may have been edited in another window.
+    fn show_window(&mut self)
+    {
+        let window = self.windows[self.window_index].clone();
+        self.switch_buffer(window.buffer);
+        self.cursor_position = window.cursor_position;
+        self.offset = window.offset;
+        self.selection_anchor = None;
+        self.move_cursor(Key::Null);
+        self.scroll();
+    }
+
+    /// Moves the cursor to the start of the 1-based `line`, or the last line
+    /// if the document is shorter.
+    pub fn go_to_line(&mut self, line: usize)
+    {
+        self.cursor_position = Position {
+            x: 0,
+            y: line.saturating_sub(1).min(self.document.len().saturating_sub(1))
+        };
+        self.scroll();
+    }
+
+    /// Closes the editor, restoring the terminal, and returns the documents
+    /// of all buffers in order.
+    pub fn into_documents(mut self) -> Vec<Document>
+    {
+        let current = Buffer {
+            document: std::mem::take(&mut self.document),
+            cursor_position: Position::default(),
+            offset: Position::default()
+        };
+        self.buffers.insert(self.buffer_index, current);
+        std::mem::take(&mut self.buffers)
+            .into_iter()
+            .map(|buffer| buffer.document)
+            .collect()
+    }
+
+    /// The document shown in the focused window.
+    pub fn document(&self) -> &Document
+    {
+        &self.document
+    }
+
+    /// The cursor position in the document.
+    pub fn cursor_position(&self) -> &Position
+    {
+        &self.cursor_position
+    }
+
+    /// Whether the user asked to quit.
+    pub fn should_quit(&self) -> bool
+    {
+        self.should_quit
+    }
+
+    /// Draws the current state to the terminal.
+    pub fn refresh_screen(&mut self) -> Result<(), std::io::Error>
+    {
+        if self.terminal.set_top_lines(u16::from(self.shows_tab_bar()))?
+        {
+            self.scroll();
+        }
+        let mut frame = self.draw_rows();
+        let start = frame.len().saturating_sub(self.overlay.len());
+        for (line, overlay) in frame[start..].iter_mut().zip(&self.overlay)
+        {
+            line.clone_from(overlay);
+        }
+        if self.shows_tab_bar()
+        {
+            frame.insert(0, self.draw_tab_bar());
+        }
+        self.branches.entry(self.branch_directory()).or_insert_with_key(|dir| status_line::git_branch(dir));
+        frame.push(self.draw_status_bar());
+        frame.push(self.draw_message_bar());
+        let area = self.window_area();
+        let x = self.document.row(self.cursor_position.y).map_or(0, |row| {
+            row.width(self.offset.x, self.cursor_position.x, self.show_whitespace)
+        });
+        let cursor = Position {
+            x: area.x + x,
+            y: area.y + self.cursor_position.y.saturating_sub(self.offset.y)
+        };
+        self.terminal.draw(&frame, &cursor)
+    }
+
+    // The `tab_bar` setting forces the tab bar on or off, by default it is
+    // shown once there are several buffers.
+    fn shows_tab_bar(&self) -> bool
+    {
+        self.config.get_bool("", "tab_bar").unwrap_or(self.buffer_count() > 1)
+    }
+
+    // The buffers as ` name ` tabs, with a `*` for unsaved changes and the
+    // shown one highlighted. Tabs that do not fit are replaced by `<` and `>`
+    // on the side they were cut from.
+    fn draw_tab_bar(&self) -> String
+    {
+        let width = self.terminal.size().width as usize;
+        let labels: Vec<String> = (0..self.buffer_count())
+            .map(|index| {
+                let document = self.buffer_document(index);
+                let name = document.display_name();
+                let mut name = match &document.filename
+                {
+                    Some(filename) => Path::new(filename)
+                        .file_name()
+                        .map_or(name.clone(), |name| name.to_string_lossy().into_owned()),
+                    None => name,
+                };
+                if cells::width(&name) > TAB_NAME_WIDTH
+                {
+                    name = cells::truncate(&name, TAB_NAME_WIDTH - 1) + "…";
+                }
+                let dirty = if document.is_dirty() { "*" } else { "" };
+                format!(" {}{} ", name, dirty)
+            })
+            .collect();
+        let (first, last) = fit_tabs(&labels, self.buffer_index, width);
+        let mut bar = String::new();
+        let mut used = 0;
+        if first > 0
+        {
+            bar.push('<');
+            used += 1;
+        }
+        for (index, label) in labels.iter().enumerate().take(last + 1).skip(first)
+        {
+            let label = cells::truncate(label, width.saturating_sub(used));
+            used += cells::width(&label);
+            if index == self.buffer_index
+            {
+                bar.push_str(&format!("{}{}{}", style::Invert, label, style::NoInvert));
+            }
+            else
+            {
+                bar.push_str(&label);
+            }
+        }
+        let more = if last + 1 < labels.len() { ">" } else { "" };
+        bar.push_str(&" ".repeat(width.saturating_sub(used + more.len())));
+        if used < width
+        {
+            bar.push_str(more);
+        }
+        format!(
+            "{}{}{}{}{}",
+            color::Fg(STATUS_FG_COLOR),
+            color::Bg(STATUS_BG_COLOR),
+            bar,
+            color::Fg(color::Reset),
+            color::Bg(color::Reset)
+        )
+    }
+
+    // The `status_line` format, or `status_line::DEFAULT_FORMAT`, expanded
+    // for the focused window.
+    fn draw_status_bar(&self) -> String
+    {
+        let width = self.terminal.size().width as usize;
+        let file_type = self.document.file_type().name();
+        let format = self.config.get(&file_type, "status_line").unwrap_or(status_line::DEFAULT_FORMAT);
+        let (left, right) = status_line::expand(format, |segment| self.status_segment(segment));
+        format!(
+            "{}{}{}{}{}",
+            color::Fg(STATUS_FG_COLOR),
+            color::Bg(STATUS_BG_COLOR),
+            cells::align(&left, &right, width),
+            color::Fg(color::Reset),
+            color::Bg(color::Reset)
+        )
+    }
+
+    // The text of a `{segment}` in the status line format. Indicators that
+    // may be missing start with a space when shown, so that they can be put
+    // right after other segments.
+    fn status_segment(&self, segment: &str) -> Option<String>
+    {
+        let document = &self.document;
+        let Position { x, y } = self.cursor_position;
+        let text = match segment
+        {
+            "name" => document.display_name(),
+            "readonly" if document.is_read_only() => " [RO]".to_string(),
+            "modified" if document.is_dirty() => " (modified)".to_string(),
+            "buffers" if self.buffer_count() > 1 => format!(" [{}/{}]", self.buffer_index + 1, self.buffer_count()),
+            "selection" => match self.selection()
+            {
+                Some((start, end)) => {
+                    let selected = document.text_between(&start, &end).graphemes(true).count();
+                    format!(" ({} selected)", selected)
+                },
+                None => String::new(),
+            },
+            "readonly" | "modified" | "buffers" => String::new(),
+            "lines" => document.len().to_string(),
+            "line" => y.saturating_add(1).to_string(),
+            "column" => x.saturating_add(1).to_string(),
+            "percent" => format!("{}%", y.saturating_add(1).min(document.len()) * 100 / document.len().max(1)),
+            "filetype" => document.file_type().name(),
+            // Documents are always read and written as UTF-8.
+            "encoding" => "utf-8".to_string(),
+            "eol" => document.line_ending().to_string(),
+            "mode" => match (document.is_read_only(), &self.selection_anchor)
+            {
+                (true, _) => "VIEW".to_string(),
+                (false, Some(_)) => "SELECT".to_string(),
+                (false, None) => "EDIT".to_string(),
+            },
+            "branch" => self.branches.get(&self.branch_directory()).cloned().flatten().unwrap_or_default(),
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    // Where the git branch of the shown document is looked up.
+    fn branch_directory(&self) -> PathBuf
+    {
+        let dir = self.document.directory();
+        if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir }
+    }
+
+    fn draw_message_bar(&self) -> String
+    {
+        let message = &self.status_message;
+        if Instant::now() - message.time < Duration::new(5, 0)
+        {
+            return cells::truncate(&message.text, self.terminal.size().width as usize);
+        }
+        String::new()
+    }
+
+    fn draw_welcome_message(&self, width: usize) -> String
+    {
+        let mut welcome_message = format!("Hecto editor -- version {}", VERSION);            
+        let len = welcome_message.len();            
+        let padding = width.saturating_sub(len) / 2;            
+        let spaces = " ".repeat(padding.saturating_sub(1));            
+        welcome_message = format!("~{}{}", spaces, welcome_message);            
+        welcome_message.truncate(width);            
+        welcome_message
+    }
+
+    // The selected part of the row at `index` and the brackets on it, as
+    // (from, to, enable, disable).
+    fn row_highlights(&self, row: &Row, index: usize, brackets: &[Position]) -> Vec<(usize, usize, String, String)>
+    {
+        let mut highlights = Vec::new();
+        if let Some((from, to)) = self.selected_range(index, row.len())
+        {
+            highlights.push((from, to, style::Invert.to_string(), style::NoInvert.to_string()));
+        }
+        for bracket in brackets.iter().filter(|bracket| bracket.y == index)
+        {
+            highlights.push((
+                bracket.x,
+                bracket.x.saturating_add(1),
+                color::Bg(MATCH_BG_COLOR).to_string(),
+                color::Bg(color::Reset).to_string()
+            ));
+        }
+        highlights.sort_by_key(|highlight| highlight.0);
+        highlights
+    }
+
+    // Draws the part of `row` visible in a window `width` cells wide that is
+    // scrolled to `offset`, padded to the full width with `fill`.
+    fn draw_row(
+        &self,
+        row: &Row,
+        highlights: Vec<(usize, usize, String, String)>,
+        offset: &Position,
+        width: usize,
+        fill: bool
+    ) -> String
+    {
+        let start = offset.x;
+        let end = row.index_at_column(start, width, self.show_whitespace);
+
+        let mut rendered = String::new();
+        let mut x = start;
+        for (from, to, enable, disable) in highlights
+        {
+            let from = from.clamp(start, end);
+            let to = to.clamp(start, end);
+            if from < x
             {
-                break;
+                continue;
             }
-            if let Err(error) = self.process_keypress()
+            rendered.push_str(&row.render(x, from, self.show_whitespace));
+            rendered.push_str(&enable);
+            rendered.push_str(&row.render(from, to, self.show_whitespace));
+            rendered.push_str(&disable);
+            x = to;
+        }
+        rendered.push_str(&row.render(x, end, self.show_whitespace));
+        if fill
+        {
+            let drawn = row.width(start, end, self.show_whitespace);
+            rendered.push_str(&" ".repeat(width.saturating_sub(drawn)));
+        }
+        rendered
+    }
+
+    fn draw_rows(&self) -> Vec<String>
+    {
+        let area = self.text_area();
+        self.layout.render(area, &mut |window, window_area| {
+            self.draw_window(window, window_area, window_area.x + window_area.width < area.width)
+        })
+    }
+
+    fn draw_window(&self, window: usize, area: Rect, fill: bool) -> Vec<String>
+    {
+        let focused = window == self.window_index;
+        let (document, offset) = if focused {
+            (&self.document, &self.offset)
+        }
+        else
+        {
+            let window = &self.windows[window];
+            (self.buffer_document(window.buffer), &window.offset)
+        };
+        // A match outside the window would not be drawn, so the search stops
+        // at its edges.
+        let visible = offset.y..offset.y.saturating_add(area.height);
+        let matching = if focused { document.matching_bracket(&self.cursor_position, visible) } else { None };
+        let brackets: Vec<Position> = match matching
+        {
+            Some(matching) => vec![self.cursor_position.clone(), matching],
+            None => Vec::new(),
+        };
+        let mut lines = Vec::new();
+        for terminal_row in 0..area.height
+        {
+            let index = offset.y.saturating_add(terminal_row);
+            let line = if let Some(row) = document.row(index)
+            {
+                // Only the focused window shows the selection and brackets.
+                let highlights = if focused { self.row_highlights(row, index, &brackets) } else { Vec::new() };
+                self.draw_row(row, highlights, offset, area.width, fill)
+            }
+            else if document.is_empty() && terminal_row == area.height / 3
+            {
+                self.draw_welcome_message(area.width)
+            }
+            else
+            {
+                "~".to_string()
+            };
+            if fill && document.row(index).is_none()
             {
-                Self::die(error);
+                lines.push(format!("{:width$}", line, width = area.width));
+            }
+            else
+            {
+                lines.push(line);
             }
         }
+        lines
     }
 
-    fn refresh_screen(&self) -> Result<(), std::io::Error>
+    // Returns the selection as an ordered (start, end) pair, if there is one.
+    fn selection(&self) -> Option<(Position, Position)>
     {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
-        
-        if self.should_quit
+        let anchor = self.selection_anchor.clone()?;
+        let cursor = self.cursor_position.clone();
+        if (anchor.y, anchor.x) <= (cursor.y, cursor.x)
         {
-            Terminal::clear_screen();
-            println!("Goodbye.\r");
+            Some((anchor, cursor))
         }
         else
         {
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y)
+            Some((cursor, anchor))
+        }
+    }
+
+    fn selected_range(&self, y: usize, len: usize) -> Option<(usize, usize)>
+    {
+        let (start, end) = self.selection()?;
+        if y < start.y || y > end.y
+        {
+            return None;
+        }
+        let from = if y == start.y { start.x } else { 0 };
+        let to = if y == end.y { end.x } else { len };
+        Some((from, to))
+    }
+
+    fn selected_lines(&self) -> (usize, usize)
+    {
+        match self.selection() {
+            Some((start, end)) => (start.y, end.y),
+            None => (self.cursor_position.y, self.cursor_position.y)
+        }
+    }
+
+    fn indent_lines(&mut self, dedent: bool)
+    {
+        if !self.editable()
+        {
+            return;
+        }
+        let (first, last) = self.selected_lines();
+        if self.document.row(first).is_none()
+        {
+            return;
+        }
+        self.document.checkpoint(&self.cursor_position);
+        for y in first..=last
+        {
+            if first != last && self.document.row(y).is_some_and(Row::is_empty)
+            {
+                continue;
+            }
+            let changed = if dedent {
+                self.document.dedent(y)
+            }
+            else
+            {
+                self.document.indent(y)
+            };
+            let shift = |position: &mut Position| {
+                if position.y == y
+                {
+                    position.x = if dedent {
+                        position.x.saturating_sub(changed)
+                    }
+                    else
+                    {
+                        position.x.saturating_add(changed)
+                    };
+                }
+            };
+            shift(&mut self.cursor_position);
+            if let Some(anchor) = &mut self.selection_anchor
+            {
+                shift(anchor);
+            }
+        }
+    }
+
+    fn toggle_comment(&mut self)
+    {
+        if !self.editable()
+        {
+            return;
+        }
+        if self.document.file_type().line_comment().is_none()
+        {
+            self.status_message = StatusMessage::from(format!(
+                "No line comments for file type: {}",
+                self.document.file_type().name()
+            ));
+            return;
+        }
+        let (first, last) = self.selected_lines();
+        if self.document.row(first).is_none()
+        {
+            return;
+        }
+        self.document.checkpoint(&self.cursor_position);
+        let edits = self.document.toggle_comment(first, last).unwrap_or_default();
+        for (y, x, delta) in edits
+        {
+            let shift = |position: &mut Position| {
+                if position.y != y || position.x < x
+                {
+                    return;
+                }
+                position.x = if delta >= 0 {
+                    position.x.saturating_add(delta.unsigned_abs())
+                }
+                else
+                {
+                    position.x.saturating_sub(delta.unsigned_abs()).max(x)
+                };
+            };
+            shift(&mut self.cursor_position);
+            if let Some(anchor) = &mut self.selection_anchor
+            {
+                shift(anchor);
+            }
+        }
+    }
+
+    fn insert_char(&mut self, c: char)
+    {
+        if !self.editable()
+        {
+            return;
+        }
+        self.selection_anchor = None;
+        let typed = c.to_string();
+        if self.auto_pair
+        {
+            let at = &self.cursor_position;
+            let previous = if at.x > 0 {
+                self.document.grapheme(&Position { x: at.x - 1, y: at.y })
+            }
+            else
+            {
+                None
+            };
+            let next = self.document.grapheme(at);
+            let is_closer = QUOTES.contains(&typed.as_str())
+                || BRACKET_PAIRS.iter().any(|(_, close)| *close == typed);
+            if is_closer && next == Some(typed.as_str())
+            {
+                self.move_cursor(Key::Right);
+                return;
+            }
+            let closer = if let Some((_, close)) = BRACKET_PAIRS.iter().find(|(open, _)| *open == typed)
+            {
+                Some(*close)
+            }
+            else if QUOTES.contains(&typed.as_str())
+                && !previous.is_some_and(|g| g.chars().all(char::is_alphanumeric) || g == typed)
+                && next.is_none_or(|g| g.trim().is_empty() || BRACKET_PAIRS.iter().any(|(_, close)| *close == g))
+            {
+                Some(typed.as_str())
+            }
+            else
+            {
+                None
+            };
+            if let Some(closer) = closer.and_then(|closer| closer.chars().next())
+            {
+                self.document.checkpoint_typing(&self.cursor_position);
+                self.document.insert(&self.cursor_position, c);
+                self.move_cursor(Key::Right);
+                self.document.insert(&self.cursor_position, closer);
+                return;
+            }
+        }
+        self.document.checkpoint_typing(&self.cursor_position);
+        self.document.insert(&self.cursor_position, c);
+        self.move_cursor(Key::Right);
+    }
+
+    fn delete_backward(&mut self)
+    {
+        if !self.editable()
+        {
+            return;
+        }
+        self.selection_anchor = None;
+        self.document.checkpoint(&self.cursor_position);
+        self.move_cursor(Key::Left);
+        let previous = self.document.grapheme(&self.cursor_position).map(str::to_string);
+        let next = self.document.grapheme(&Position {
+            x: self.cursor_position.x.saturating_add(1),
+            y: self.cursor_position.y
+        });
+        let is_empty_pair = self.auto_pair
+            && previous.is_some_and(|previous| {
+                BRACKET_PAIRS.iter().any(|(open, close)| *open == previous && next == Some(*close))
+                    || (QUOTES.contains(&previous.as_str()) && next == Some(previous.as_str()))
             });
+        if is_empty_pair
+        {
+            self.document.delete(&self.cursor_position);
+        }
+        self.document.delete(&self.cursor_position);
+    }
+
+    fn jump_to_matching_bracket(&mut self)
+    {
+        if let Some(position) = self.document.matching_bracket(&self.cursor_position, 0..self.document.len())
+        {
+            self.cursor_position = position;
+        }
+    }
+
+    fn save(&mut self)
+    {
+        if self.document.filename.is_none() || self.document.is_read_only()
+        {
+            let prompt = if self.document.is_read_only() {
+                "File is read-only. Save as: "
+            }
+            else
+            {
+                "Save as: "
+            };
+            self.save_as(prompt);
+            return;
+        }
+        if let Some(change) = self.document.disk_change()
+        {
+            if !self.resolve_disk_change(change).unwrap_or(false)
+            {
+                return;
+            }
+        }
+        self.write_document(None);
+    }
+
+    // Saves to a new file and keeps editing that one from now on.
+    fn save_as(&mut self, prompt: &str)
+    {
+        match self.prompt_for_target(prompt)
+        {
+            Some(name) => {
+                if self.write_document(Some(&name))
+                {
+                    if let Some(warning) = lock_document(&mut self.document)
+                    {
+                        self.status_message = StatusMessage::from(warning);
+                    }
+                }
+            },
+            None => self.status_message = StatusMessage::from("Save aborted.".to_string()),
+        }
+    }
+
+    // Saves to another file but keeps editing the current one.
+    fn write_copy(&mut self)
+    {
+        let Some(name) = self.prompt_for_target("Write copy to: ") else {
+            self.status_message = StatusMessage::from("Write aborted.".to_string());
+            return;
+        };
+        self.status_message = match self.document.write_copy(&name)
+        {
+            Ok(()) => StatusMessage::from(format!("Wrote copy to {}.", name)),
+            Err(error) => StatusMessage::from(format!("Could not write {}: {}", name, error)),
+        };
+    }
+
+    fn write_selection(&mut self)
+    {
+        let Some((start, end)) = self.selection() else {
+            self.status_message = StatusMessage::from("Nothing selected (Ctrl-Space starts a selection).".to_string());
+            return;
+        };
+        let Some(name) = self.prompt_for_target("Write selection to: ") else {
+            self.status_message = StatusMessage::from("Write aborted.".to_string());
+            return;
+        };
+        self.status_message = match self.document.write_range(&start, &end, &name)
+        {
+            Ok(()) => StatusMessage::from(format!("Wrote selection to {}.", name)),
+            Err(error) => StatusMessage::from(format!("Could not write {}: {}", name, error)),
+        };
+    }
+
+    // Asks for a path relative to the current file, completing it on Tab.
+    fn open_file(&mut self)
+    {
+        let base = self.document.directory();
+        let input = self
+            .prompt("Open (Tab to complete): ", |_, key, input| {
+                if key == Key::Char('\t')
+                {
+                    *input = files::complete(&base, input);
+                }
+            })
+            .unwrap_or(None);
+        match input
+        {
+            Some(input) => {
+                self.open_path(&files::resolve(&base, &input));
+            },
+            None => self.status_message = StatusMessage::from("Open aborted.".to_string()),
+        }
+    }
+
+    // Offers the files under the working directory, ranked by how well they
+    // match the typed pattern, with Up and Down to pick one.
+    fn find_file(&mut self)
+    {
+        let root = self.project_root.clone();
+        let paths = files::project_files(&root, FINDER_FILE_LIMIT);
+        let mut matches = files::rank(&paths, "");
+        let mut selected = 0;
+        self.draw_finder(&matches, selected);
+        let query = self
+            .prompt_input("Find file (Up/Down to pick): ", |editor, key, query| {
+                match key
+                {
+                    Key::Up => selected = (selected + 1).min(matches.len().saturating_sub(1)),
+                    Key::Down => selected = selected.saturating_sub(1),
+                    _ => {
+                        matches = files::rank(&paths, query);
+                        selected = 0;
+                    },
+                }
+                editor.draw_finder(&matches, selected);
+            })
+            .unwrap_or(None);
+        self.overlay.clear();
+        match (query, matches.get(selected))
+        {
+            (Some(_), Some(path)) => {
+                self.open_path(&root.join(path));
+            },
+            (Some(query), None) => self.status_message = StatusMessage::from(format!("No file matches {}.", query)),
+            (None, _) => (),
+        }
+    }
+
+    // Searches all files under the working directory for a literal text or,
+    // with `regex`, a regular expression, and lists the hits in a buffer.
+    fn search_project(&mut self, regex: bool)
+    {
+        let prompt = if regex { "Search project for regex: " } else { "Search project: " };
+        let Some(query) = self.prompt(prompt, |_, _, _| {}).unwrap_or(None) else {
+            return;
+        };
+        let pattern = if regex { query.clone() } else { regex::escape(&query) };
+        let pattern = match Regex::new(&pattern)
+        {
+            Ok(pattern) => pattern,
+            Err(error) => {
+                // The parse errors span several lines, the last one says what is wrong.
+                let reason = error.to_string().lines().last().unwrap_or_default().to_string();
+                self.status_message = StatusMessage::from(format!("Invalid regex: {}", reason));
+                return;
+            },
+        };
+        // One result more than shown tells whether the limit cut any off.
+        let mut results = files::search(&self.project_root, &pattern, SEARCH_RESULT_LIMIT + 1);
+        if results.is_empty()
+        {
+            self.status_message = StatusMessage::from(format!("No matches for {}.", query));
+            return;
+        }
+        let limited = if results.len() > SEARCH_RESULT_LIMIT { " (limit reached)" } else { "" };
+        results.truncate(SEARCH_RESULT_LIMIT);
+        self.status_message = StatusMessage::from(format!(
+            "{} matches{}. Enter jumps to one.",
+            results.len(),
+            limited
+        ));
+        self.add_buffer(Document::search_results(&self.project_root, &query, &results));
+        self.switch_buffer(self.buffer_count() - 1);
+    }
+
+    // Lists the best `matches` in the overlay, best at the bottom next to the
+    // prompt, with the `selected` one highlighted.
+    fn draw_finder(&mut self, matches: &[&String], selected: usize)
+    {
+        let width = self.terminal.size().width as usize;
+        let count = FINDER_LINES.min(self.terminal.size().height as usize);
+        let first = selected.saturating_sub(count.saturating_sub(1));
+        self.overlay = matches
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(count)
+            .map(|(index, path)| {
+                let line = cells::truncate(&format!("  {}", path), width);
+                if index == selected
+                {
+                    format!("{}{}{}", style::Invert, cells::align(&line, "", width), style::NoInvert)
+                }
+                else
+                {
+                    line
+                }
+            })
+            .rev()
+            .collect();
+    }
+
+    // Shows the file at `path` in a buffer, reusing the one that already
+    // edits it, or lists the directory at `path`. Returns whether it is shown.
+    fn open_path(&mut self, path: &Path) -> bool
+    {
+        if let Some(index) = self.find_buffer(path)
+        {
+            self.switch_buffer(index);
+            return true;
+        }
+        let name = path.to_string_lossy();
+        let document = if path.is_dir() {
+            Document::directory_listing(path)
+        }
+        else
+        {
+            match Document::open(&name)
+            {
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Document::new_file(&name)),
+                result => result,
+            }
+        };
+        let mut document = match document
+        {
+            Ok(document) => document,
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Could not open {}: {}", name, error));
+                return false;
+            },
+        };
+        if let Some(warning) = lock_document(&mut document)
+        {
+            self.status_message = StatusMessage::from(warning);
+        }
+        self.add_buffer(document);
+        self.switch_buffer(self.buffer_count() - 1);
+        true
+    }
+
+    // Opens the entry under the cursor of a listing.
+    fn open_listing_entry(&mut self, listing: Listing)
+    {
+        let Some(row) = self.document.row(self.cursor_position.y) else {
+            return;
+        };
+        let entry = String::from_utf8_lossy(row.as_bytes()).into_owned();
+        match listing
+        {
+            Listing::Directory(dir) => self.open_directory_entry(&dir, &entry),
+            Listing::SearchResults { root, .. } => {
+                if let Some((path, line)) = files::parse_search_result(&entry)
+                {
+                    if self.open_path(&root.join(path))
+                    {
+                        self.go_to_line(line);
+                    }
+                }
+            },
+        }
+    }
+
+    // Directories replace the listing, so that browsing does not pile up
+    // buffers.
+    fn open_directory_entry(&mut self, dir: &Path, entry: &str)
+    {
+        let path: PathBuf = match entry
+        {
+            "../" => dir.parent().unwrap_or(dir).to_path_buf(),
+            _ => dir.join(entry.trim_end_matches('/')),
+        };
+        if !path.is_dir()
+        {
+            self.open_path(&path);
+            return;
+        }
+        match Document::directory_listing(&path)
+        {
+            Ok(document) => {
+                self.document = document;
+                self.cursor_position = Position::default();
+                self.offset = Position::default();
+            },
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("Could not open {}: {}", path.display(), error));
+            },
+        }
+    }
+
+    // Asks for a file name to write to, confirming before replacing a file
+    // other than the current one.
+    fn prompt_for_target(&mut self, prompt: &str) -> Option<String>
+    {
+        let name = self.prompt(prompt, |_, _, _| {}).unwrap_or(None)?;
+        // The same file may be named differently, as in `./notes.txt`.
+        let canonical = |name: &str| fs::canonicalize(name).ok();
+        let is_current = canonical(&name).is_some_and(|target| {
+            self.document.filename.as_deref().and_then(canonical) == Some(target)
+        });
+        if !is_current && Path::new(&name).exists()
+        {
+            let question = format!("{} already exists. Overwrite? (y/n)", name);
+            if self.ask(&question, &['y', 'n']).unwrap_or(None) != Some('y')
+            {
+                return None;
+            }
+        }
+        Some(name)
+    }
+
+    // Saves the document, to `target` instead of its own file if given, and
+    // tells whether that succeeded.
+    fn write_document(&mut self, target: Option<&str>) -> bool
+    {
+        let file_type = target.map_or_else(|| self.document.file_type().name(), |name| FileType::from(name).name());
+        let options = self.config.save_options(&file_type);
+        let result = match target
+        {
+            Some(name) => self.document.save_as(name, &options),
+            None => self.document.save(&options),
+        };
+        let saved = result.is_ok();
+        match result
+        {
+            Ok(report) if report.is_empty() => {
+                self.status_message = StatusMessage::from("File saved successfully.".to_string());
+            },
+            Ok(report) => {
+                self.status_message = StatusMessage::from(format!(
+                    "File saved successfully ({}).",
+                    report.join(", ")
+                ));
+            },
+            Err(_) => self.status_message = StatusMessage::from("Error writing file!".to_string()),
+        }
+        self.cursor_position.y = self.cursor_position.y.min(self.document.len());
+        self.move_cursor(Key::Null);
+        saved
+    }
+
+    // Asks about every buffer whose file changed on disk since it was last
+    // asked about, showing each buffer while asking.
+    fn check_disk_changes(&mut self) -> Result<(), std::io::Error>
+    {
+        for index in 0..self.buffer_count()
+        {
+            if self.buffer_document(index).new_disk_change().is_none()
+            {
+                continue;
+            }
+            self.switch_buffer(index);
+            if let Some(change) = self.document.new_disk_change()
+            {
+                if self.resolve_disk_change(change)?
+                {
+                    self.write_document(None);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Asks what to do about the file having changed on disk, and returns
+    // whether it should be overwritten with the buffer.
+    fn resolve_disk_change(&mut self, change: DiskChange) -> Result<bool, std::io::Error>
+    {
+        let answer = match change
+        {
+            DiskChange::Modified(_) => self.ask(
+                "File changed on disk: (r)eload, (o)verwrite, (d)iff, Esc to keep editing",
+                &['r', 'o', 'd']
+            )?,
+            DiskChange::Deleted => self.ask("File deleted on disk: (o)verwrite to restore it, Esc to keep editing", &['o'])?,
+        };
+        match answer
+        {
+            Some('r') => {
+                self.document.checkpoint(&self.cursor_position);
+                self.status_message = match self.document.reload()
+                {
+                    Ok(()) => StatusMessage::from("Reloaded from disk (Ctrl-Z to undo).".to_string()),
+                    Err(error) => StatusMessage::from(format!("Could not reload: {}", error)),
+                };
+                self.selection_anchor = None;
+                self.cursor_position.y = self.cursor_position.y.min(self.document.len());
+                self.move_cursor(Key::Null);
+                self.scroll();
+            },
+            Some('o') => return Ok(true),
+            Some('d') => match self.document.diff_with_disk()
+            {
+                Ok(diff) if diff.is_empty() => {
+                    self.status_message = StatusMessage::from("No differences.".to_string());
+                },
+                Ok(diff) => {
+                    self.document.dismiss_change(change);
+                    self.add_buffer(Document::from_text(&diff));
+                    self.switch_buffer(self.buffer_count() - 1);
+                },
+                Err(error) => self.status_message = StatusMessage::from(format!("Could not diff: {}", error)),
+            },
+            _ => self.document.dismiss_change(change),
         }
-
-        Terminal::cursor_show();
-        Terminal::flush()
+        Ok(false)
     }
 
-    fn draw_status_bar(&self)
+    // Shows `question` until one of `answers` or Esc is pressed, and returns
+    // the answer.
+    fn ask(&mut self, question: &str, answers: &[char]) -> Result<Option<char>, std::io::Error>
     {
-        let mut status;
-        let width = self.terminal.size().width as usize;
-        let modified_indicator = if self.document.is_dirty() 
+        self.status_message = StatusMessage::from(question.to_string());
+        let answer = loop
         {
-            " (modified)"
-        }
-        else
-        {
-            ""
+            self.refresh_screen()?;
+            match self.read_key()?
+            {
+                Key::Char(c) if answers.contains(&c.to_ascii_lowercase()) => break Some(c.to_ascii_lowercase()),
+                Key::Esc => break None,
+                _ => (),
+            }
         };
-        let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.filename {
-            file_name = name.clone();
-            file_name.truncate(20);
-        }
-        status = format!(
-            "{} - {} lines{}", 
-            file_name, 
-            self.document.len(),
-            modified_indicator
-        );
-        let line_indicator = format!(
-            "{}/{}",
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
-        );
-        let len = status.len() + line_indicator.len();
-        status.push_str(&" ".repeat(width.saturating_sub(len)));
-        status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        println!("{}\r", status);
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
+        self.status_message = StatusMessage::from(String::new());
+        Ok(answer)
     }
 
-    fn draw_message_bar(&self)
+    // Whether the shown document may be edited. Commands that edit check this
+    // first, so that a read-only document tells how to save a copy instead.
+    fn editable(&mut self) -> bool
     {
-        Terminal::clear_current_line();
-        let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0)
+        if self.document.is_read_only()
         {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);  
+            self.status_message = StatusMessage::from(
+                "File is read-only. Press Ctrl-S to save a copy elsewhere.".to_string()
+            );
+            return false;
         }
+        true
     }
 
-    fn draw_welcome_message(&self)
+    fn process_keypress(&mut self) -> Result<(), std::io::Error>
     {
-        let mut welcome_message = format!("Hecto editor -- version {}", VERSION);            
-        let width = self.terminal.size().width as usize;            
-        let len = welcome_message.len();            
-        let padding = width.saturating_sub(len) / 2;            
-        let spaces = " ".repeat(padding.saturating_sub(1));            
-        welcome_message = format!("~{}{}", spaces, welcome_message);            
-        welcome_message.truncate(width);            
-        println!("{}\r", welcome_message);
+        let event = self.read_event()?; // ? means that if there is an error, auto return it (otherwise continue)
+        self.process_event(event)
     }
 
-    fn draw_row(&self, row: &Row)
+    /// Applies one input event. Commands that open a prompt, like find and
+    /// save-as, read the rest of their input from the terminal.
+    pub fn process_event(&mut self, event: Event) -> Result<(), std::io::Error>
     {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row)
+        let result = self.handle_event(event);
+        self.follow_line_shifts();
+        result
     }
 
-    fn draw_rows(&self)
+    // Keeps the other windows on the shown buffer at the same text when rows
+    // are added or removed above them. Their cursor is clamped once they get
+    // the focus.
+    fn follow_line_shifts(&mut self)
     {
-        let size = self.terminal.size();
-        for terminal_row in 0..size.height
+        let shifts = self.document.take_line_shifts();
+        for (index, window) in self.windows.iter_mut().enumerate()
         {
-            Terminal::clear_current_line();
-            if let Some(row) = self.document.row(self.offset.y.saturating_add(terminal_row as usize))
-            {
-                self.draw_row(row);
-            }
-            else if self.document.is_empty() && terminal_row == size.height / 3
+            if index == self.window_index || window.buffer != self.buffer_index
             {
-                self.draw_welcome_message();
+                continue;
             }
-            else
+            for &(from, delta) in &shifts
             {
-                println!("~\r");
+                for y in [&mut window.cursor_position.y, &mut window.offset.y]
+                {
+                    if *y >= from
+                    {
+                        *y = y.saturating_add_signed(delta);
+                    }
+                }
             }
         }
     }
 
-    fn save(&mut self)
+    fn handle_event(&mut self, event: Event) -> Result<(), std::io::Error>
     {
-        if self.document.filename.is_none()
+        let pressed_key = match event
+        {
+            Event::Key(key) => key,
+            Event::Mouse(event) => {
+                self.process_mouse(event);
+                self.scroll();
+                return Ok(());
+            },
+            Event::Resize => return Ok(()),
+            Event::Focus | Event::Idle => {
+                self.branches.clear();
+                return self.check_disk_changes();
+            },
+        };
+        if pressed_key == Key::Char('\n')
         {
-            let new_name = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
-            if new_name.is_none()
+            if let Some(listing) = self.document.listing().cloned()
             {
-                self.status_message = StatusMessage::from("Save aborted.".to_string());
-                return;
+                self.open_listing_entry(listing);
+                self.scroll();
+                return Ok(());
             }
-            self.document.filename = new_name;
-        }
-
-        if self.document.save().is_ok()
-        {
-            self.status_message = StatusMessage::from("File saved successfully.".to_string());
-        }
-        else
-        {
-            self.status_message = StatusMessage::from("Error writing file!".to_string());
         }
-    }
-
-    fn process_keypress(&mut self) -> Result<(), std::io::Error>
-    {
-        let pressed_key = Terminal::read_key()?;  // ? means that if there is an error, auto return it (otherwise continue)
         match pressed_key
         {
             Key::Ctrl('q') => {
                 self.quit_times -= 1;
-                if self.document.is_dirty() && self.quit_times > 0
+                if self.is_any_dirty() && self.quit_times > 0
                 {
                     self.status_message = StatusMessage::from(format!(
-                        "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                        "WARNING! There are unsaved changes. Press Ctrl-Q {} more times to quit.",
                         self.quit_times
                     ));
                     return Ok(());
@@ -265,19 +1519,54 @@ impl Editor
                 self.should_quit = true
             },
             Key::Ctrl('s') => self.save(),
+            Key::Ctrl('o') => self.open_file(),
+            Key::Ctrl('p') => self.find_file(),
+            Key::Ctrl('g') => self.search_project(false),
+            Key::Alt('g') => self.search_project(true),
+            Key::Alt('s') => self.save_as("Save as: "),
+            Key::Alt('c') => self.write_copy(),
+            Key::Alt('r') => self.write_selection(),
             Key::Ctrl('f') => self.search(),
-            Key::Char(c) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(Key::Right);
-            },
-            Key::Delete => self.document.delete(&self.cursor_position),
-            Key::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0
+            Key::Ctrl('z') if self.editable() => {
+                if let Some(position) = self.document.undo()
                 {
-                    self.move_cursor(Key::Left);
-                    self.document.delete(&self.cursor_position);
+                    self.selection_anchor = None;
+                    self.cursor_position = position;
+                    self.move_cursor(Key::Null);
+                }
+            },
+            Key::Null => {
+                self.selection_anchor = if self.selection_anchor.is_some() {
+                    None
                 }
+                else
+                {
+                    Some(self.cursor_position.clone())
+                };
             },
+            Key::Esc => self.selection_anchor = None,
+            Key::Char('\t') if self.selection_anchor.is_some() => self.indent_lines(false),
+            Key::Alt('>') => self.indent_lines(false),
+            Key::BackTab | Key::Alt('<') => self.indent_lines(true),
+            Key::Ctrl('b') => self.jump_to_matching_bracket(),
+            // Most terminals send Ctrl-/ as 0x1f, which termion reports as Ctrl-7.
+            Key::Ctrl('7') | Key::Alt('/') => self.toggle_comment(),
+            Key::Alt('w') => self.show_whitespace = !self.show_whitespace,
+            Key::Ctrl('w') => self.window_command()?,
+            Key::Alt('.') => self.cycle_buffer(true),
+            Key::Alt(',') => self.cycle_buffer(false),
+            Key::Alt('p') => {
+                self.auto_pair = !self.auto_pair;
+                let state = if self.auto_pair { "on" } else { "off" };
+                self.status_message = StatusMessage::from(format!("Auto-pairing {}.", state));
+            },
+            Key::Char(c) => self.insert_char(c),
+            Key::Delete if self.editable() => {
+                self.selection_anchor = None;
+                self.document.checkpoint(&self.cursor_position);
+                self.document.delete(&self.cursor_position);
+            },
+            Key::Backspace if self.cursor_position.x > 0 || self.cursor_position.y > 0 => self.delete_backward(),
             Key::Up | Key::Down | Key::Left | Key::Right 
             | Key::PageUp | Key::PageDown | Key::Home | Key::End => self.move_cursor(pressed_key),
             _ => ()
@@ -291,50 +1580,148 @@ impl Editor
         Ok(())
     }
 
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &mut String),
+    {
+        Ok(self.prompt_input(prompt, callback)?.filter(|result| !result.is_empty()))
+    }
+
+    // Like `prompt`, but only returns `None` for Esc, so that Enter on an
+    // empty input can still be told apart.
+    fn prompt_input<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
     where
-        C: FnMut(&mut Self, Key, &String),
+        C: FnMut(&mut Self, Key, &mut String),
     {
         let mut result = String::new();
         loop
         {
             self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_screen()?;
-            let key = Terminal::read_key()?;
+            let key = self.read_key()?;
             match key
             {
-                Key::Backspace => result.truncate(result.len().saturating_sub(1)),
-                Key::Char('\n') => break,
-                Key::Char(c) => {
-                    if !c.is_control()
-                    {
-                        result.push(c);
-                    }
+                Key::Backspace => {
+                    result.pop();
                 },
+                Key::Char('\n') => break,
+                Key::Char(c) if !c.is_control() => result.push(c),
                 Key::Esc => {
-                    result.truncate(0);
-                    break;
+                    self.status_message = StatusMessage::from(String::new());
+                    return Ok(None);
                 },
                 _ => (),
             }
-            callback(self, key, &result);
+            callback(self, key, &mut result);
         }
 
         self.status_message = StatusMessage::from(String::new());
-        
-        if result.is_empty()
+        Ok(Some(result))
+    }
+
+    // Reads the next key or mouse event, redrawing for every resize that
+    // happens meanwhile so that the screen stays correct even while a prompt
+    // is open.
+    fn read_event(&mut self) -> Result<Event, std::io::Error>
+    {
+        loop
         {
-            return Ok(None);
+            match self.terminal.read_event()?
+            {
+                Event::Resize => {
+                    self.scroll();
+                    self.refresh_screen()?;
+                },
+                event => return Ok(event),
+            }
         }
-        Ok(Some(result))
+    }
+
+    fn read_key(&mut self) -> Result<Key, std::io::Error>
+    {
+        loop
+        {
+            if let Event::Key(key) = self.read_event()?
+            {
+                return Ok(key);
+            }
+        }
+    }
+
+    fn process_mouse(&mut self, event: MouseEvent)
+    {
+        match event
+        {
+            MouseEvent::Press(MouseButton::Left, x, y) => {
+                let cell = (usize::from(x.saturating_sub(1)), usize::from(y.saturating_sub(1)));
+                let Some(window) = self.window_at(cell.0, cell.1) else {
+                    return;
+                };
+                self.focus_window(window);
+                self.cursor_position = self.position_at(x, y);
+                self.selection_anchor = Some(self.cursor_position.clone());
+            },
+            MouseEvent::Hold(x, y) => {
+                if self.selection_anchor.is_none()
+                {
+                    self.selection_anchor = Some(self.cursor_position.clone());
+                }
+                self.cursor_position = self.position_at(x, y);
+            },
+            MouseEvent::Release(..) => {
+                if self.selection_anchor.as_ref() == Some(&self.cursor_position)
+                {
+                    self.selection_anchor = None;
+                }
+            },
+            MouseEvent::Press(button @ (MouseButton::WheelUp | MouseButton::WheelDown), x, y) => {
+                let cell = (usize::from(x.saturating_sub(1)), usize::from(y.saturating_sub(1)));
+                if let Some(window) = self.window_at(cell.0, cell.1)
+                {
+                    self.focus_window(window);
+                }
+                self.scroll_view(button == MouseButton::WheelDown);
+            },
+            MouseEvent::Press(..) => (),
+        }
+    }
+
+    // Converts one-based screen coordinates into a position in the focused
+    // window, accounting for the scroll offset and the rendered width of tabs.
+    fn position_at(&self, x: u16, y: u16) -> Position
+    {
+        let area = self.window_area();
+        let y = (y.saturating_sub(1) as usize).saturating_sub(area.y).min(area.height);
+        let y = self.offset.y.saturating_add(y).min(self.document.len());
+        let column = (x.saturating_sub(1) as usize).saturating_sub(area.x);
+        let x = self.document.row(y).map_or(0, |row| {
+            row.index_at_column(self.offset.x, column, self.show_whitespace)
+        });
+        Position { x, y }
+    }
+
+    // Moves the view by a few lines and drags the cursor along if it would
+    // otherwise leave the screen.
+    fn scroll_view(&mut self, down: bool)
+    {
+        let height = self.window_area().height;
+        self.offset.y = if down {
+            self.offset.y.saturating_add(WHEEL_LINES).min(self.document.len().saturating_sub(1))
+        }
+        else
+        {
+            self.offset.y.saturating_sub(WHEEL_LINES)
+        };
+        let last_visible = self.offset.y.saturating_add(height.saturating_sub(1));
+        self.cursor_position.y = self.cursor_position.y.clamp(self.offset.y, last_visible);
+        self.move_cursor(Key::Null);
     }
 
     fn scroll(&mut self)
     {
         let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
+        let Rect { width, height, .. } = self.window_area();
+        let offset = &mut self.offset;
         if y < offset.y
         {
             offset.y = y;
@@ -347,15 +1734,24 @@ impl Editor
         {
             offset.x = x;
         }
-        else if x >= offset.x.saturating_add(width)
+        else if let Some(row) = self.document.row(y)
         {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+            // Scrolls by cells, so that all of the cell under the cursor,
+            // which may be a tab or a wide character, stays in the window.
+            let show_whitespace = self.show_whitespace;
+            let cursor_end = row.width(0, x.saturating_add(1), show_whitespace)
+                .max(row.width(0, x, show_whitespace).saturating_add(1));
+            if cursor_end > width
+            {
+                let start = row.index_at_column(0, cursor_end - width - 1, show_whitespace).saturating_add(1);
+                offset.x = offset.x.max(start);
+            }
         }
     }
 
     fn move_cursor(&mut self, key: Key)
     {
-        let terminal_height = self.terminal.size().height as usize;
+        let terminal_height = self.window_area().height;
         let Position { mut x, mut y } = self.cursor_position;
         let height = self.document.len();
         let mut width = if let Some(row) = self.document.row(y) {
@@ -368,12 +1764,7 @@ impl Editor
         match key 
         {
             Key::Up => y = y.saturating_sub(1),
-            Key::Down => {
-                if y < height
-                {
-                    y = y.saturating_add(1);
-                }
-            },
+            Key::Down if y < height => y = y.saturating_add(1),
             Key::Left => {
                 if x > 0
                 {
@@ -460,7 +1851,7 @@ impl Editor
                         Key::Left | Key::Up => direction = SearchDirection::Backward,
                         _ => direction = SearchDirection::Forward,
                     }
-                    if let Some(position) = editor.document.find(&query, &editor.cursor_position, direction) 
+                    if let Some(position) = editor.document.find(query, &editor.cursor_position, direction) 
                     {
                         editor.cursor_position = position;
                         editor.scroll();
@@ -478,10 +1869,602 @@ impl Editor
             self.scroll();
         }
     }
+}
+
+// Marks the file of `document` as being edited here, and returns a warning
+// if another instance already edits it.
+fn lock_document(document: &mut Document) -> Option<String>
+{
+    let owner = document.lock()?;
+    let (user, pid) = (owner.user.clone(), owner.pid);
+    Some(format!(
+        "WARNING! {} is already being edited by {} (PID {}).",
+        document.filename.as_deref().unwrap_or_default(), user, pid
+    ))
+}
+
+// The range of tabs to show around `current` so that they fit into `width`
+// along with `<` and `>` for the cut off ones, taking tabs on the right first.
+fn fit_tabs(labels: &[String], current: usize, width: usize) -> (usize, usize)
+{
+    let len = |index: usize| cells::width(&labels[index]);
+    let (mut first, mut last) = (current, current);
+    let mut used = len(current);
+    loop
+    {
+        let markers = usize::from(first > 0) + usize::from(last + 1 < labels.len());
+        if last + 1 < labels.len() && used + len(last + 1) + markers <= width
+        {
+            last += 1;
+            used += len(last);
+        }
+        else if first > 0 && used + len(first - 1) + markers <= width
+        {
+            first -= 1;
+            used += len(first);
+        }
+        else
+        {
+            return (first, last);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::backend::{Grid, GridBackend};
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    fn editor(document: Document, keys: &[Key]) -> (Editor, Rc<RefCell<Grid>>)
+    {
+        let backend = GridBackend::new(40, 10, keys.iter().map(|key| Event::Key(*key)).collect());
+        let screen = backend.grid();
+        let terminal = Terminal::new(Box::new(backend)).unwrap();
+        (Editor::new(terminal, document, Config::default()), screen)
+    }
+
+    fn run(document: Document, keys: &[Key]) -> (Editor, Rc<RefCell<Grid>>)
+    {
+        let (mut editor, screen) = editor(document, keys);
+        editor.run().unwrap();
+        (editor, screen)
+    }
+
+    fn typed(text: &str) -> Vec<Key>
+    {
+        text.chars().map(Key::Char).collect()
+    }
+
+    // Opens `contents` as a file, plays `keys` followed by save and quit, and
+    // returns what ended up on disk.
+    fn edit_file(contents: &str, keys: Vec<Key>) -> String
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.txt");
+        fs::write(&path, contents).unwrap();
+        let document = Document::open(path.to_str().unwrap()).unwrap();
+        let mut keys = keys;
+        keys.extend([Key::Ctrl('s'), Key::Ctrl('q')]);
+        run(document, &keys);
+        fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn draws_typed_text_and_status_bar()
+    {
+        let quit = Key::Ctrl('q');
+        let (editor, screen) = run(Document::default(), &[Key::Char('h'), Key::Char('i'), Key::Esc, quit, quit, quit]);
+        let screen = screen.borrow();
+        assert!(editor.should_quit);
+        assert_eq!(screen.lines[0], "hi");
+        assert!(screen.lines[8].starts_with("[No Name] - 1 lines (modified)"));
+        assert_eq!((screen.cursor.x, screen.cursor.y), (2, 0));
+    }
+
+    #[test]
+    fn draws_welcome_message_for_empty_document()
+    {
+        let (_, screen) = run(Document::default(), &[Key::Ctrl('q')]);
+        let screen = screen.borrow();
+        assert!(screen.lines[2].starts_with('~'));
+        assert!(screen.lines[2].contains("Hecto editor -- version"));
+        assert_eq!(screen.lines[0], "~");
+    }
+
+    #[test]
+    fn saves_edits_to_opened_file()
+    {
+        let mut keys = vec![Key::End];
+        keys.extend(typed("!"));
+        keys.extend([Key::Down, Key::Home]);
+        keys.extend(typed("> "));
+        assert_eq!(edit_file("hello\nwörld\n", keys), "hello!\n> wörld\n");
+    }
+
+    #[test]
+    fn prompts_for_a_name_when_saving_a_new_buffer()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.txt");
+        let mut keys = typed("hi");
+        keys.push(Key::Ctrl('s'));
+        keys.extend(typed(path.to_str().unwrap()));
+        keys.extend([Key::Char('\n'), Key::Ctrl('q')]);
+        let (editor, _) = run(Document::default(), &keys);
+        assert!(!editor.document.is_dirty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hi\n");
+    }
+
+    #[test]
+    fn search_moves_the_cursor_to_the_match()
+    {
+        let mut keys = vec![Key::Ctrl('f')];
+        keys.extend(typed("gam"));
+        keys.push(Key::Char('\n'));
+        keys.extend(typed(">"));
+        assert_eq!(edit_file("alpha\nbeta gamma\n", keys), "alpha\nbeta >gamma\n");
+    }
+
+    #[test]
+    fn backward_search_finds_earlier_rows()
+    {
+        let mut keys = vec![Key::Down, Key::Down, Key::Ctrl('f')];
+        keys.extend(typed("é"));
+        keys.extend([Key::Up, Key::Char('\n')]);
+        keys.extend(typed("|"));
+        assert_eq!(edit_file("xé\ny\nzé\n", keys), "x|é\ny\nzé\n");
+    }
+
+    #[test]
+    fn backspace_at_row_start_joins_rows()
+    {
+        let keys = vec![Key::Down, Key::Backspace];
+        assert_eq!(edit_file("ab\ncd\n", keys), "abcd\n");
+    }
+
+    #[test]
+    fn indenting_a_selection_is_undone_in_one_step()
+    {
+        let keys = vec![Key::Null, Key::Down, Key::End, Key::Char('\t')];
+        assert_eq!(edit_file("a\nb\n", keys.clone()), "    a\n    b\n");
+        let mut undone = keys;
+        undone.push(Key::Ctrl('z'));
+        assert_eq!(edit_file("a\nb\n", undone), "a\nb\n");
+    }
+
+    #[test]
+    fn writes_a_selection_of_an_empty_document()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("part.txt");
+        let mut keys = vec![Key::Null, Key::Alt('r')];
+        keys.extend(typed(path.to_str().unwrap()));
+        keys.push(Key::Char('\n'));
+        let (mut editor, _) = editor(Document::default(), &keys);
+        editor.run().unwrap_err();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn selecting_in_an_empty_document_draws_the_status_line()
+    {
+        let (mut editor, screen) = editor(Document::default(), &[Key::Null]);
+        editor.run().unwrap_err();
+        assert!(screen.borrow().lines[8].contains("(0 selected)"));
+    }
+
+    #[test]
+    fn typed_runs_are_undone_together()
+    {
+        let mut keys = typed("ab");
+        keys.push(Key::Right);
+        keys.extend(typed("cd"));
+        keys.push(Key::Ctrl('z'));
+        assert_eq!(edit_file("x\n", keys), "abx\n");
+    }
+
+    #[test]
+    fn auto_pairs_brackets_and_skips_typed_closers()
+    {
+        assert_eq!(edit_file("", typed("f(x)")), "f(x)\n");
+        let mut keys = typed("[");
+        keys.push(Key::Backspace);
+        assert_eq!(edit_file("", keys), "");
+    }
+
+    #[test]
+    fn switches_between_buffers_keeping_their_cursors()
+    {
+        let (mut editor, screen) = editor(Document::from_text("one\n"), &[]);
+        editor.add_buffer(Document::from_text("a\nb\nc\n"));
+        let keys = [Key::End, Key::Alt('.'), Key::Down, Key::Char('>'), Key::Alt(','), Key::Char('!')];
+        for key in keys
+        {
+            editor.process_event(Event::Key(key)).unwrap();
+        }
+        editor.refresh_screen().unwrap();
+        assert_eq!(editor.document().text(), "one!\n");
+        assert!(screen.borrow().lines[8].contains("[1/2]"));
+        editor.process_event(Event::Key(Key::Alt('.'))).unwrap();
+        assert_eq!(editor.document().text(), "a\n>b\nc\n");
+        assert_eq!(editor.cursor_position(), &Position { x: 1, y: 1 });
+        let texts: Vec<String> = editor.into_documents().iter().map(Document::text).collect();
+        assert_eq!(texts, ["one!\n", "a\n>b\nc\n"]);
+    }
+
+    #[test]
+    fn goes_to_a_line_clamped_to_the_document()
+    {
+        let (mut editor, _) = editor(Document::from_text("a\nb\nc\n"), &[]);
+        editor.go_to_line(2);
+        assert_eq!(editor.cursor_position().y, 1);
+        editor.go_to_line(99);
+        assert_eq!(editor.cursor_position().y, 2);
+    }
+
+    #[test]
+    fn asks_before_saving_over_external_changes()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.txt");
+        fs::write(&path, "a\n").unwrap();
+        let document = Document::open(path.to_str().unwrap()).unwrap();
+        fs::write(&path, "a\nb\n").unwrap();
+
+        let (mut diffing, screen) = editor(document, &[Key::Char('x'), Key::Ctrl('s'), Key::Char('d')]);
+        diffing.run().unwrap_err();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+        assert_eq!(diffing.buffer_count(), 2);
+        assert!(diffing.document().text().contains("+xa\n"));
+        assert!(screen.borrow().lines.iter().any(|line| line == "-b"));
+
+        let document = Document::open(path.to_str().unwrap()).unwrap();
+        fs::write(&path, "c\n").unwrap();
+        let (mut overwriting, _) = editor(document, &[Key::Char('x'), Key::Ctrl('s'), Key::Char('o')]);
+        overwriting.run().unwrap_err();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xa\nb\n");
+
+        let document = Document::open(path.to_str().unwrap()).unwrap();
+        fs::write(&path, "reloaded\n").unwrap();
+        let (mut editor, _) = editor(document, &[Key::Char('x'), Key::Ctrl('s'), Key::Char('r')]);
+        editor.run().unwrap_err();
+        assert_eq!(editor.document().text(), "reloaded\n");
+    }
+
+    #[test]
+    fn asks_about_changes_to_every_buffer_when_idle()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+        fs::write(&first, "1\n").unwrap();
+        fs::write(&second, "2\n").unwrap();
+        let (mut editor, screen) = editor(Document::open(first.to_str().unwrap()).unwrap(), &[Key::Esc, Key::Char('r')]);
+        editor.add_buffer(Document::open(second.to_str().unwrap()).unwrap());
+        fs::remove_file(&first).unwrap();
+        fs::write(&second, "changed\n").unwrap();
+        editor.process_event(Event::Idle).unwrap();
+        assert_eq!(editor.document().text(), "changed\n");
+        editor.process_event(Event::Idle).unwrap();
+
+        fs::remove_file(&second).unwrap();
+        editor.process_event(Event::Idle).unwrap_err();
+        assert!(screen.borrow().lines[9].starts_with("File deleted on disk"));
+    }
+
+    #[test]
+    fn saves_as_writes_copies_and_writes_selections()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "one\ntwo\n").unwrap();
+        let target = |name: &str| {
+            let mut keys = typed(dir.path().join(name).to_str().unwrap());
+            keys.push(Key::Char('\n'));
+            keys
+        };
+
+        let mut keys = vec![Key::Char('>'), Key::Alt('c')];
+        keys.extend(target("copy/copy.txt"));
+        keys.extend([Key::Home, Key::Null, Key::Down, Key::Alt('r')]);
+        keys.extend(target("part.txt"));
+        keys.push(Key::Alt('s'));
+        keys.extend(target("copy/copy.txt"));
+        keys.push(Key::Char('n'));
+        keys.push(Key::Alt('s'));
+        keys.extend(target("moved.txt"));
+        let held = crate::FileLock::acquire(dir.path().join("moved.txt").to_str().unwrap()).unwrap();
+        let (mut editor, _) = editor(Document::open(path).unwrap(), &keys);
+        editor.run().unwrap_err();
+        assert!(editor.status_message.text.contains("moved.txt is already being edited"));
+        drop(held);
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("test.txt"), "one\ntwo\n");
+        assert_eq!(read("copy/copy.txt"), ">one\ntwo\n");
+        assert_eq!(read("part.txt"), ">one\n");
+        assert_eq!(read("moved.txt"), ">one\ntwo\n");
+        assert!(editor.document().filename.as_ref().unwrap().ends_with("moved.txt"));
+        assert!(!editor.document().is_dirty());
+    }
+
+    #[test]
+    fn save_as_keeps_the_file_when_the_write_fails()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.txt");
+        fs::write(&path, "one\n").unwrap();
+        let mut keys = vec![Key::Char('>'), Key::Alt('s')];
+        keys.extend(typed(dir.path().join(".").join("test.txt").to_str().unwrap()));
+        keys.extend([Key::Char('\n'), Key::Char('>'), Key::Alt('s')]);
+        keys.extend(typed(path.join("test.txt").to_str().unwrap()));
+        keys.push(Key::Char('\n'));
+        let (mut renaming, _) = editor(Document::open(path.to_str().unwrap()).unwrap(), &keys);
+        renaming.run().unwrap_err();
+        assert_eq!(renaming.status_message.text, "Error writing file!");
+        assert_eq!(fs::read_to_string(&path).unwrap(), ">one\n");
+        assert!(renaming.document().filename.as_ref().unwrap().ends_with("test.txt"));
+        assert!(!renaming.document().filename.as_ref().unwrap().ends_with("test.txt/test.txt"));
+        assert!(renaming.document().is_dirty());
+    }
+
+    #[test]
+    fn read_only_documents_block_edits_and_save_elsewhere()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.txt");
+        let copy = dir.path().join("copy.txt");
+        fs::write(&path, "x\n").unwrap();
+        let mut document = Document::open(path.to_str().unwrap()).unwrap();
+        document.set_read_only(true);
+        let mut keys = vec![Key::Char('y'), Key::Ctrl('s')];
+        keys.extend(typed(copy.to_str().unwrap()));
+        keys.extend([Key::Char('\n'), Key::Char('z'), Key::Ctrl('s')]);
+        let (mut viewer, screen) = editor(document, &keys[..1]);
+        viewer.run().unwrap_err();
+        assert!(screen.borrow().lines[8].contains("[RO]"));
+        assert!(screen.borrow().lines[9].starts_with("File is read-only."));
+        assert!(!viewer.document().is_dirty());
+
+        let mut document = Document::open(path.to_str().unwrap()).unwrap();
+        document.set_read_only(true);
+        let (mut editor, _) = editor(document, &keys);
+        editor.run().unwrap_err();
+        assert!(!editor.document().is_read_only());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x\n");
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "zx\n");
+    }
+
+    #[test]
+    fn opens_completed_paths_and_directory_listings()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("notes")).unwrap();
+        fs::write(dir.path().join("notes/todo.txt"), "milk\n").unwrap();
+        fs::write(dir.path().join("main.txt"), "main\n").unwrap();
+        let document = Document::open(dir.path().join("main.txt").to_str().unwrap()).unwrap();
+        let mut keys = vec![Key::Ctrl('o'), Key::Char('n'), Key::Char('\t'), Key::Char('t'), Key::Char('\t')];
+        keys.push(Key::Char('\n'));
+        keys.extend([Key::Ctrl('o'), Key::Char('.'), Key::Char('\n')]);
+        keys.extend([Key::Char('\n'), Key::Down, Key::Down, Key::Char('\n')]);
+        let (mut editor, _) = editor(document, &keys);
+        editor.run().unwrap_err();
+        assert_eq!(editor.buffer_count(), 3);
+        assert_eq!(editor.document().text(), "main\n");
+        editor.process_event(Event::Key(Key::Alt(','))).unwrap();
+        assert_eq!(editor.document().text(), "../\nnotes/\nmain.txt\n");
+        editor.process_event(Event::Key(Key::Alt(','))).unwrap();
+        assert_eq!(editor.document().text(), "milk\n");
+    }
+
+    #[test]
+    fn finds_project_files_by_fuzzy_pattern()
+    {
+        let (mut finder, screen) = editor(Document::default(), &[]);
+        let paths: Vec<String> = ["a.rs", "b.rs", "c.rs"].iter().map(|path| path.to_string()).collect();
+        finder.draw_finder(&paths.iter().collect::<Vec<_>>(), 1);
+        finder.refresh_screen().unwrap();
+        let lines = screen.borrow().lines.clone();
+        assert_eq!(lines[5], "  c.rs");
+        assert_eq!(lines[6].trim_end(), "  b.rs");
+        assert_eq!(lines[7], "  a.rs");
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/lock.rs"), "locked\n").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        let mut keys = vec![Key::Ctrl('p')];
+        keys.extend(typed("srclockr"));
+        keys.push(Key::Char('\n'));
+        let (mut opener, _) = editor(Document::default(), &keys);
+        opener.project_root = dir.path().to_path_buf();
+        opener.run().unwrap_err();
+        assert_eq!(opener.buffer_count(), 2);
+        assert!(opener.document().filename.as_ref().unwrap().ends_with("src/lock.rs"));
+        assert_eq!(opener.document().text(), "locked\n");
+        assert!(opener.overlay.is_empty());
+
+        let keys = [Key::Ctrl('p'), Key::Up, Key::Char('\n'), Key::Ctrl('p'), Key::Esc];
+        let (mut editor, _) = editor(Document::default(), &keys);
+        editor.project_root = dir.path().to_path_buf();
+        editor.run().unwrap_err();
+        assert_eq!(editor.buffer_count(), 2);
+        assert!(editor.document().filename.as_ref().unwrap().ends_with(".rs"));
+    }
+
+    #[test]
+    fn lists_project_search_results_and_jumps_to_them()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/a.rs"), "fn a()\n{\n    let size = 1;\n}\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "size\n").unwrap();
+        let mut keys = vec![Key::Alt('g')];
+        keys.extend(typed("let s[i]ze\n\n"));
+        let (mut searching, _) = editor(Document::default(), &keys);
+        searching.project_root = dir.path().to_path_buf();
+        searching.run().unwrap_err();
+        assert_eq!(searching.buffer_count(), 3);
+        assert!(searching.document().filename.as_ref().unwrap().ends_with("src/a.rs"));
+        assert_eq!(searching.cursor_position().y, 2);
+        searching.process_event(Event::Key(Key::Alt(','))).unwrap();
+        assert_eq!(searching.document().text(), "src/a.rs:3:     let size = 1;");
+
+        let mut keys = vec![Key::Alt('g')];
+        keys.extend(typed("(\n"));
+        let (mut editor, screen) = editor(Document::default(), &keys);
+        editor.run().unwrap_err();
+        assert!(screen.borrow().lines[9].starts_with("Invalid regex: error: unclosed group"));
+    }
+
+    #[test]
+    fn scrolls_horizontally_by_cells()
+    {
+        let text = format!("{}x\n", "\t".repeat(12));
+        let (_, screen) = run(Document::from_text(&text), &[Key::End, Key::Ctrl('q')]);
+        assert!(screen.borrow().cursor.x < 40);
+        assert!(screen.borrow().lines[0].trim_end().ends_with('x'));
+
+        let keys = [Key::Ctrl('w'), Key::Char('v'), Key::End, Key::Ctrl('q')];
+        let (_, screen) = run(Document::from_text(&text), &keys);
+        assert!(screen.borrow().cursor.x < 40);
+        assert!(screen.borrow().lines[0].split('│').nth(1).unwrap().trim_end().ends_with('x'));
+    }
+
+    #[test]
+    fn wide_characters_stay_inside_their_window()
+    {
+        let keys = [Key::Ctrl('w'), Key::Char('v'), Key::Ctrl('q')];
+        let (_, screen) = run(Document::from_text(&"日本語".repeat(10)), &keys);
+        let line = screen.borrow().lines[0].clone();
+        assert_eq!(cells::width(&line), 40);
+        assert_eq!(line.find('│').map(|index| cells::width(&line[..index])), Some(19));
+    }
+
+    #[test]
+    fn splits_show_buffers_in_several_windows()
+    {
+        let mut keys = vec![
+            Key::Ctrl('w'), Key::Char('v'), Key::Char('X'),
+            Key::Ctrl('w'), Key::Left, Key::Ctrl('w'), Key::Char('s'), Key::Alt('.'),
+        ];
+        let (mut split, screen) = editor(Document::from_text("one\ntwo\n"), &keys);
+        split.add_buffer(Document::from_text("other\n"));
+        split.run().unwrap_err();
+        {
+            let screen = screen.borrow();
+            assert_eq!(screen.lines[1], format!("Xone{}│Xone", " ".repeat(15)));
+            assert_eq!(screen.lines[4], format!("{}│~", "─".repeat(19)));
+            assert_eq!(screen.lines[5], format!("other{}│~", " ".repeat(14)));
+            assert_eq!((screen.cursor.x, screen.cursor.y), (0, 5));
+        }
+
+        keys.extend([Key::Ctrl('w'), Key::Char('c'), Key::Ctrl('w'), Key::Char('>'), Key::Char('Y')]);
+        let (mut editor, screen) = editor(Document::from_text("one\ntwo\n"), &keys);
+        editor.add_buffer(Document::from_text("other\n"));
+        editor.run().unwrap_err();
+        assert_eq!(screen.borrow().lines[1], format!("YXone{}│YXone", " ".repeat(16)));
+        assert_eq!(screen.borrow().lines[5], format!("~{}│~", " ".repeat(20)));
+        assert_eq!(editor.windows.len(), 2);
+    }
+
+    #[test]
+    fn other_windows_follow_rows_added_above_them()
+    {
+        let keys = [
+            Key::Down, Key::Down, Key::Ctrl('w'), Key::Char('s'),
+            Key::Up, Key::Up, Key::Char('\n'), Key::Char('\n'), Key::Backspace
+        ];
+        let (mut editor, _) = editor(Document::from_text("one\ntwo\nthree\n"), &keys);
+        editor.run().unwrap_err();
+        let other = &editor.windows[1 - editor.window_index];
+        assert_eq!(other.cursor_position.y, 3);
+    }
+
+    #[test]
+    fn fits_tabs_around_the_current_one()
+    {
+        let labels: Vec<String> = ["aaaa", "bbbb", "cccc", "dddd"].iter().map(|label| label.to_string()).collect();
+        assert_eq!(fit_tabs(&labels, 0, 80), (0, 3));
+        assert_eq!(fit_tabs(&labels, 0, 10), (0, 1));
+        assert_eq!(fit_tabs(&labels, 3, 10), (2, 3));
+        assert_eq!(fit_tabs(&labels, 2, 6), (2, 2));
+        assert_eq!(fit_tabs(&labels, 1, 2), (1, 1));
+    }
+
+    #[test]
+    fn draws_a_tab_bar_for_several_buffers()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "x\n").unwrap();
+        let (mut editor, screen) = editor(Document::open(path.to_str().unwrap()).unwrap(), &[]);
+        editor.refresh_screen().unwrap();
+        assert_eq!(screen.borrow().lines[0], "x");
+
+        editor.add_buffer(Document::from_text("y\n"));
+        editor.process_event(Event::Key(Key::Char('!'))).unwrap();
+        editor.refresh_screen().unwrap();
+        assert_eq!(screen.borrow().lines[0].trim_end(), " notes.txt*  [No Name]");
+        assert_eq!(screen.borrow().lines[1], "!x");
+        assert_eq!(screen.borrow().cursor.y, 1);
+        assert_eq!(editor.terminal.size().height, 7);
+
+        for _ in 0..4
+        {
+            editor.add_buffer(Document::from_text("z\n"));
+        }
+        let backend = GridBackend::new(30, 10, Vec::new());
+        let screen = backend.grid();
+        editor.terminal = Terminal::new(Box::new(backend)).unwrap();
+        editor.process_event(Event::Key(Key::Alt(','))).unwrap();
+        editor.refresh_screen().unwrap();
+        assert_eq!(screen.borrow().lines[0], format!("< [No Name]  [No Name] {}", " ".repeat(7)));
+    }
+
+    #[test]
+    fn draws_a_configured_status_line()
+    {
+        let config = Config::parse(
+            "status_line = {name} {mode} {line}:{column} {percent}{selection}{=}{eol} {encoding} {filetype}"
+        ).unwrap();
+        let backend = GridBackend::new(60, 10, Vec::new());
+        let screen = backend.grid();
+        let terminal = Terminal::new(Box::new(backend)).unwrap();
+        let mut editor = Editor::new(terminal, Document::new_file("日本.rs"), config);
+        for key in typed("abc").into_iter().chain([Key::Home, Key::Null, Key::End])
+        {
+            editor.process_event(Event::Key(key)).unwrap();
+        }
+        editor.refresh_screen().unwrap();
+        let expected = format!("日本.rs SELECT 1:4 100% (3 selected){}LF utf-8 Rust", " ".repeat(11));
+        assert_eq!(screen.borrow().lines[8], expected);
+    }
 
-    fn die(e: std::io::Error)
+    #[test]
+    fn reads_the_branch_again_when_idle()
     {
-        Terminal::clear_screen();
-        panic!("{}", e);
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        let backend = GridBackend::new(20, 5, Vec::new());
+        let screen = backend.grid();
+        let terminal = Terminal::new(Box::new(backend)).unwrap();
+        let document = Document::new_file(dir.path().join("a.txt").to_str().unwrap());
+        let mut editor = Editor::new(terminal, document, Config::parse("status_line = <{branch}>").unwrap());
+        editor.refresh_screen().unwrap();
+        assert!(screen.borrow().lines[3].starts_with("<main>"));
+
+        fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/topic\n").unwrap();
+        editor.refresh_screen().unwrap();
+        assert!(screen.borrow().lines[3].starts_with("<main>"));
+        editor.process_event(Event::Idle).unwrap();
+        editor.refresh_screen().unwrap();
+        assert!(screen.borrow().lines[3].starts_with("<topic>"));
     }
 }
@@ -0,0 +1,272 @@
+use crate::FileLock;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Larger files are skipped by searches, as they are rarely source code.
+const SEARCH_FILE_SIZE_LIMIT: u64 = 4 * 1024 * 1024;
+
+/// What a generated, read-only buffer lists, so that Enter can open the
+/// entry under the cursor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Listing
+{
+    /// The entries of a directory, one per line.
+    Directory(PathBuf),
+    /// The hits of a search in the files under a directory, as
+    /// `path:line: text` lines with paths relative to it.
+    SearchResults
+    {
+        /// The directory that was searched.
+        root: PathBuf,
+        /// What was searched for.
+        query: String
+    }
+}
+
+// Where a path typed relative to `base` points to. An empty base stands for
+// the working directory.
+pub fn resolve(base: &Path, input: &str) -> PathBuf
+{
+    let path = Path::new(input);
+    if path.is_absolute() || base.as_os_str().is_empty()
+    {
+        return path.to_path_buf();
+    }
+    base.join(path)
+}
+
+// Completes the last component of `input`, a path relative to `base`, as far
+// as all matching entries agree. Hidden entries are only offered once a `.`
+// has been typed, and a single matching directory gets its trailing `/`.
+pub fn complete(base: &Path, input: &str) -> String
+{
+    let (directory, prefix) = match input.rfind('/')
+    {
+        Some(index) => input.split_at(index + 1),
+        None => ("", input),
+    };
+    let dir = resolve(base, directory);
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir.as_path() };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return input.to_string();
+    };
+    let mut matches: Vec<(String, bool)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok().filter(|name| !FileLock::is_marker(name))?;
+            let is_dir = entry.path().is_dir();
+            Some((name, is_dir))
+        })
+        .filter(|(name, _)| name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.')))
+        .collect();
+    matches.sort();
+    let Some((first, is_dir)) = matches.first() else {
+        return input.to_string();
+    };
+    let mut common = first.clone();
+    for (name, _) in &matches[1..]
+    {
+        let shared = common
+            .char_indices()
+            .zip(name.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(common.len().min(name.len()), |((index, _), _)| index);
+        common.truncate(shared);
+    }
+    if matches.len() == 1 && *is_dir
+    {
+        common.push('/');
+    }
+    format!("{}{}", directory, common)
+}
+
+// The entries of `dir` as lines, directories first and marked with a `/`,
+// starting with `../` to go up.
+pub fn list_directory(dir: &Path) -> Result<String, std::io::Error>
+{
+    let mut entries: Vec<(bool, String)> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok().filter(|name| !FileLock::is_marker(name))?;
+            Some((!entry.path().is_dir(), name))
+        })
+        .collect();
+    entries.sort();
+    let mut listing = String::from("../\n");
+    for (is_file, name) in entries
+    {
+        listing.push_str(&name);
+        if !is_file
+        {
+            listing.push('/');
+        }
+        listing.push('\n');
+    }
+    Ok(listing)
+}
+
+// The files under `root` as paths relative to it, skipping hidden files and
+// anything matched by `.gitignore` and `.ignore` files. Walking stops after
+// `limit` files so that huge trees stay responsive.
+pub fn project_files(root: &Path, limit: usize) -> Vec<String>
+{
+    let mut paths: Vec<String> = WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+        .filter_map(|entry| Some(entry.path().strip_prefix(root).ok()?.to_str()?.to_string()))
+        .filter(|path| !FileLock::is_marker(path.rsplit('/').next().unwrap_or(path)))
+        .take(limit)
+        .collect();
+    paths.sort();
+    paths
+}
+
+// The `paths` that fuzzily match `query`, best first. Among equal scores the
+// shorter path wins, as it is usually the one meant.
+pub fn rank<'a>(paths: &'a [String], query: &str) -> Vec<&'a String>
+{
+    let matcher = SkimMatcherV2::default().smart_case();
+    let mut scored: Vec<(i64, &String)> = paths
+        .iter()
+        .filter_map(|path| Some((matcher.fuzzy_match(path, query)?, path)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.len().cmp(&b.1.len())));
+    scored.into_iter().map(|(_, path)| path).collect()
+}
+
+// The lines of the files under `root` matching `pattern`, as
+// `path:line: text`. Files that are not UTF-8 text, contain NUL bytes or are
+// larger than `SEARCH_FILE_SIZE_LIMIT` are skipped, and at most `limit` lines
+// are returned.
+pub fn search(root: &Path, pattern: &Regex, limit: usize) -> Vec<String>
+{
+    let mut results = Vec::new();
+    for path in project_files(root, usize::MAX)
+    {
+        let file = root.join(&path);
+        if fs::metadata(&file).map_or(true, |metadata| metadata.len() > SEARCH_FILE_SIZE_LIMIT)
+        {
+            continue;
+        }
+        let Ok(contents) = fs::read_to_string(&file) else {
+            continue;
+        };
+        if contents.contains('\0')
+        {
+            continue;
+        }
+        for (index, line) in contents.lines().enumerate().filter(|(_, line)| pattern.is_match(line))
+        {
+            if results.len() == limit
+            {
+                return results;
+            }
+            results.push(format!("{}:{}: {}", path, index + 1, line));
+        }
+    }
+    results
+}
+
+// The path and 1-based line of a `path:line: text` search result. The first
+// `:line:` is taken, so paths may contain colons but no `:digits:`.
+pub fn parse_search_result(result: &str) -> Option<(&str, usize)>
+{
+    result.match_indices(':').find_map(|(index, _)| {
+        let (line, _) = result[index + 1..].split_once(':')?;
+        Some((&result[..index], line.parse().ok()?))
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn completes_unique_and_common_prefixes()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        fs::write(dir.path().join("src/mangle.rs"), "").unwrap();
+        fs::write(dir.path().join(".hidden"), "").unwrap();
+        let base = dir.path();
+        assert_eq!(complete(base, "s"), "src/");
+        assert_eq!(complete(base, "src/m"), "src/ma");
+        assert_eq!(complete(base, "src/mai"), "src/main.rs");
+        assert_eq!(complete(base, "x"), "x");
+        assert_eq!(complete(base, ""), "src/");
+        assert_eq!(complete(base, ".h"), ".hidden");
+        let absolute = format!("{}/sr", base.display());
+        assert_eq!(complete(Path::new(""), &absolute), format!("{}/src/", base.display()));
+    }
+
+    #[test]
+    fn lists_directories_first()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        fs::write(dir.path().join(".a.txt.hecto-lock"), "").unwrap();
+        fs::create_dir(dir.path().join("z")).unwrap();
+        assert_eq!(list_directory(dir.path()).unwrap(), "../\nz/\na.txt\n");
+    }
+
+    #[test]
+    fn walks_the_project_respecting_gitignore()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/bin")).unwrap();
+        fs::create_dir(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "target/\n*.log\n").unwrap();
+        for file in ["src/main.rs", "src/bin/tool.rs", "target/out.rs", "debug.log", "README"]
+        {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        assert_eq!(project_files(dir.path(), 100), ["README", "src/bin/tool.rs", "src/main.rs"]);
+        assert_eq!(project_files(dir.path(), 1).len(), 1);
+    }
+
+    #[test]
+    fn ranks_paths_by_fuzzy_score()
+    {
+        let paths: Vec<String> = ["src/editor.rs", "docs/edit/readme.md", "src/document.rs"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        assert_eq!(rank(&paths, "edrs"), ["src/editor.rs"]);
+        assert_eq!(rank(&paths, "srcdoc"), ["src/document.rs"]);
+        assert_eq!(rank(&paths, ""), ["src/editor.rs", "src/document.rs", "docs/edit/readme.md"]);
+        assert!(rank(&paths, "xyz").is_empty());
+    }
+
+    #[test]
+    fn searches_project_files_line_by_line()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/a.rs"), "fn main()\n{\n    main_loop();\n}\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "domain\n").unwrap();
+        fs::write(dir.path().join("image.bin"), [0xff, 0xfe, b'm']).unwrap();
+        fs::write(dir.path().join("data.bin"), "main\0").unwrap();
+        let mut large = "main\n".repeat(SEARCH_FILE_SIZE_LIMIT as usize / 5);
+        large.push_str("main\n");
+        fs::write(dir.path().join("large.txt"), large).unwrap();
+        let results = search(dir.path(), &Regex::new(r"\bmain").unwrap(), 100);
+        assert_eq!(results, ["src/a.rs:1: fn main()", "src/a.rs:3:     main_loop();"]);
+        assert_eq!(search(dir.path(), &Regex::new("main").unwrap(), 2).len(), 2);
+    }
+
+    #[test]
+    fn parses_search_results()
+    {
+        assert_eq!(parse_search_result("src/a.rs:12: x: 1"), Some(("src/a.rs", 12)));
+        assert_eq!(parse_search_result("c:/a:b.rs:3: y"), Some(("c:/a:b.rs", 3)));
+        assert_eq!(parse_search_result("../"), None);
+    }
+}
@@ -0,0 +1,64 @@
+/// The language of a file, as far as editing commands care.
+pub struct FileType
+{
+    name: String,
+    line_comment: Option<&'static str>
+}
+
+impl Default for FileType
+{
+    fn default() -> Self
+    {
+        Self {
+            name: String::from("No filetype"),
+            line_comment: None
+        }
+    }
+}
+
+impl FileType
+{
+    /// The name shown in the status line, e.g. `Rust`.
+    pub fn name(&self) -> String
+    {
+        self.name.clone()
+    }
+
+    /// What starts a line comment, if the language has one.
+    pub fn line_comment(&self) -> Option<&'static str>
+    {
+        self.line_comment
+    }
+
+    /// Detects the file type from the extension or base name of `file_name`.
+    pub fn from(file_name: &str) -> Self
+    {
+        let base_name = file_name.rsplit('/').next().unwrap_or(file_name);
+        let extension = base_name.rsplit_once('.').map_or("", |(_, extension)| extension);
+        let (name, line_comment) = match (base_name, extension)
+        {
+            (_, "rs") => ("Rust", "//"),
+            (_, "c" | "h") => ("C", "//"),
+            (_, "cpp" | "cc" | "hpp") => ("C++", "//"),
+            (_, "go") => ("Go", "//"),
+            (_, "java") => ("Java", "//"),
+            (_, "js" | "jsx") => ("JavaScript", "//"),
+            (_, "ts" | "tsx") => ("TypeScript", "//"),
+            (_, "py") => ("Python", "#"),
+            (_, "rb") => ("Ruby", "#"),
+            (_, "sh" | "bash" | "zsh") => ("Shell", "#"),
+            (_, "toml") => ("TOML", "#"),
+            (_, "yml" | "yaml") => ("YAML", "#"),
+            ("Makefile" | "makefile", _) | (_, "mk") => ("Makefile", "#"),
+            ("Dockerfile", _) => ("Dockerfile", "#"),
+            (_, "sql") => ("SQL", "--"),
+            (_, "lua") => ("Lua", "--"),
+            (_, "hs") => ("Haskell", "--"),
+            _ => return Self::default(),
+        };
+        Self {
+            name: String::from(name),
+            line_comment: Some(line_comment)
+        }
+    }
+}
@@ -0,0 +1,373 @@
+/// How a split divides its area: `Horizontal` stacks the windows above each
+/// other, `Vertical` puts them side by side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitDirection
+{
+    Horizontal,
+    Vertical
+}
+
+/// Where to look for a neighboring window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side
+{
+    Left,
+    Right,
+    Above,
+    Below
+}
+
+/// A rectangle of cells on the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect
+{
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Rect
+{
+    pub fn contains(&self, x: usize, y: usize) -> bool
+    {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+const MIN_SHARE: usize = 10;
+const MAX_SHARE: usize = 90;
+
+enum Node
+{
+    Window(usize),
+    // `share` is the percentage of the space going to `first`.
+    Split
+    {
+        direction: SplitDirection,
+        share: usize,
+        first: Box<Node>,
+        second: Box<Node>
+    }
+}
+
+impl Node
+{
+    fn first_window(&self) -> usize
+    {
+        match self
+        {
+            Node::Window(window) => *window,
+            Node::Split { first, .. } => first.first_window(),
+        }
+    }
+
+    fn is_window(&self, window: usize) -> bool
+    {
+        matches!(self, Node::Window(id) if *id == window)
+    }
+}
+
+/// The arrangement of windows, numbered from 0, as a tree of splits. Each
+/// split leaves a line or column between its halves for a separator.
+pub struct Layout
+{
+    root: Node
+}
+
+impl Default for Layout
+{
+    fn default() -> Self
+    {
+        Self { root: Node::Window(0) }
+    }
+}
+
+impl Layout
+{
+    /// Splits `window` in half, giving the second half to `new_window`.
+    pub fn split(&mut self, window: usize, new_window: usize, direction: SplitDirection)
+    {
+        if let Some(node) = Self::find(&mut self.root, window)
+        {
+            *node = Node::Split {
+                direction,
+                share: 50,
+                first: Box::new(Node::Window(window)),
+                second: Box::new(Node::Window(new_window))
+            };
+        }
+    }
+
+    fn find(node: &mut Node, window: usize) -> Option<&mut Node>
+    {
+        match node
+        {
+            Node::Window(id) if *id == window => Some(node),
+            Node::Window(_) => None,
+            Node::Split { first, second, .. } => Self::find(first, window).or_else(|| Self::find(second, window)),
+        }
+    }
+
+    /// Removes `window`, giving its space to its sibling, and numbers the
+    /// windows after it one lower. Returns the window that should get the
+    /// focus, or `None` if `window` is the only one.
+    pub fn close(&mut self, window: usize) -> Option<usize>
+    {
+        let focus = Self::remove(&mut self.root, window)?;
+        Self::renumber(&mut self.root, window);
+        Some(if focus > window { focus - 1 } else { focus })
+    }
+
+    fn remove(node: &mut Node, window: usize) -> Option<usize>
+    {
+        let Node::Split { first, second, .. } = node else {
+            return None;
+        };
+        let sibling = if first.is_window(window) {
+            std::mem::replace(second.as_mut(), Node::Window(0))
+        }
+        else if second.is_window(window)
+        {
+            std::mem::replace(first.as_mut(), Node::Window(0))
+        }
+        else
+        {
+            return Self::remove(first, window).or_else(|| Self::remove(second, window));
+        };
+        *node = sibling;
+        Some(node.first_window())
+    }
+
+    fn renumber(node: &mut Node, removed: usize)
+    {
+        match node
+        {
+            Node::Window(id) if *id > removed => *id -= 1,
+            Node::Window(_) => (),
+            Node::Split { first, second, .. } => {
+                Self::renumber(first, removed);
+                Self::renumber(second, removed);
+            },
+        }
+    }
+
+    /// Moves the nearest split in `direction` around `window` by `delta`
+    /// percent, growing `window` for positive values. Returns whether there
+    /// was such a split.
+    pub fn resize(&mut self, window: usize, direction: SplitDirection, delta: isize) -> bool
+    {
+        Self::resize_node(&mut self.root, window, direction, delta) == Some(true)
+    }
+
+    // `None` if `window` is not in `node`, otherwise whether a split was
+    // resized.
+    fn resize_node(node: &mut Node, window: usize, direction: SplitDirection, delta: isize) -> Option<bool>
+    {
+        let Node::Split { direction: split_direction, share, first, second } = node else {
+            return node.is_window(window).then_some(false);
+        };
+        let in_first = match Self::resize_node(first, window, direction, delta)
+        {
+            Some(resized) => {
+                if resized
+                {
+                    return Some(true);
+                }
+                true
+            },
+            None => {
+                if Self::resize_node(second, window, direction, delta)?
+                {
+                    return Some(true);
+                }
+                false
+            },
+        };
+        if *split_direction != direction
+        {
+            return Some(false);
+        }
+        let delta = if in_first { delta } else { -delta };
+        *share = share.saturating_add_signed(delta).clamp(MIN_SHARE, MAX_SHARE);
+        Some(true)
+    }
+
+    /// The area of every window when the layout fills `area`.
+    pub fn areas(&self, area: Rect) -> Vec<(usize, Rect)>
+    {
+        let mut areas = Vec::new();
+        Self::collect_areas(&self.root, area, &mut areas);
+        areas
+    }
+
+    fn collect_areas(node: &Node, area: Rect, areas: &mut Vec<(usize, Rect)>)
+    {
+        match node
+        {
+            Node::Window(window) => areas.push((*window, area)),
+            Node::Split { direction, share, first, second } => {
+                let (first_area, second_area) = Self::divide(area, *direction, *share);
+                Self::collect_areas(first, first_area, areas);
+                Self::collect_areas(second, second_area, areas);
+            },
+        }
+    }
+
+    // Both halves of `area`, leaving one line or column between them. The
+    // first half keeps at least one cell while there is room.
+    fn divide(area: Rect, direction: SplitDirection, share: usize) -> (Rect, Rect)
+    {
+        let total = match direction
+        {
+            SplitDirection::Horizontal => area.height,
+            SplitDirection::Vertical => area.width,
+        };
+        let available = total.saturating_sub(1);
+        let first = (available * share / 100).max(1).min(available);
+        let second = available - first;
+        match direction
+        {
+            SplitDirection::Horizontal => (
+                Rect { height: first, ..area },
+                Rect { y: area.y + first + 1, height: second, ..area }
+            ),
+            SplitDirection::Vertical => (
+                Rect { width: first, ..area },
+                Rect { x: area.x + first + 1, width: second, ..area }
+            ),
+        }
+    }
+
+    /// The window next to `window` on `side`, preferring the one sharing the
+    /// longest edge with it.
+    pub fn neighbor(&self, area: Rect, window: usize, side: Side) -> Option<usize>
+    {
+        let areas = self.areas(area);
+        let (_, current) = areas.iter().find(|(id, _)| *id == window)?;
+        let overlap = |a: (usize, usize), b: (usize, usize)| a.1.min(b.1).saturating_sub(a.0.max(b.0));
+        areas
+            .iter()
+            .filter_map(|(id, other)| {
+                let horizontal = overlap((current.y, current.y + current.height), (other.y, other.y + other.height));
+                let vertical = overlap((current.x, current.x + current.width), (other.x, other.x + other.width));
+                let shared = match side
+                {
+                    Side::Left if other.x + other.width + 1 == current.x => horizontal,
+                    Side::Right if current.x + current.width + 1 == other.x => horizontal,
+                    Side::Above if other.y + other.height + 1 == current.y => vertical,
+                    Side::Below if current.y + current.height + 1 == other.y => vertical,
+                    _ => 0,
+                };
+                (shared > 0).then_some((shared, *id))
+            })
+            .min_by_key(|(shared, id)| (usize::MAX - shared, *id))
+            .map(|(_, id)| id)
+    }
+
+    /// The lines of `area` with every window drawn by `draw` and separators
+    /// between them. `draw` gets the window and its area, and must return
+    /// `height` lines exactly `width` cells wide, except that lines of areas
+    /// reaching the right edge of `area` may be shorter.
+    pub fn render<F>(&self, area: Rect, draw: &mut F) -> Vec<String>
+    where
+        F: FnMut(usize, Rect) -> Vec<String>,
+    {
+        Self::render_node(&self.root, area, draw)
+    }
+
+    fn render_node<F>(node: &Node, area: Rect, draw: &mut F) -> Vec<String>
+    where
+        F: FnMut(usize, Rect) -> Vec<String>,
+    {
+        let Node::Split { direction, share, first, second } = node else {
+            return draw(node.first_window(), area);
+        };
+        let (first_area, second_area) = Self::divide(area, *direction, *share);
+        let mut lines = Self::render_node(first, first_area, draw);
+        let second_lines = Self::render_node(second, second_area, draw);
+        match direction
+        {
+            SplitDirection::Horizontal => {
+                if area.height > 0
+                {
+                    lines.push("─".repeat(area.width));
+                }
+                lines.extend(second_lines);
+            },
+            SplitDirection::Vertical => {
+                for (line, second_line) in lines.iter_mut().zip(second_lines)
+                {
+                    line.push('│');
+                    line.push_str(&second_line);
+                }
+            },
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const SCREEN: Rect = Rect { x: 0, y: 0, width: 21, height: 11 };
+
+    // Windows 0 | 1 side by side, with 1 split into 1 above 2.
+    fn three_windows() -> Layout
+    {
+        let mut layout = Layout::default();
+        layout.split(0, 1, SplitDirection::Vertical);
+        layout.split(1, 2, SplitDirection::Horizontal);
+        layout
+    }
+
+    #[test]
+    fn divides_the_area_between_windows()
+    {
+        let layout = three_windows();
+        assert_eq!(layout.areas(SCREEN), [
+            (0, Rect { x: 0, y: 0, width: 10, height: 11 }),
+            (1, Rect { x: 11, y: 0, width: 10, height: 5 }),
+            (2, Rect { x: 11, y: 6, width: 10, height: 5 })
+        ]);
+        let lines = layout.render(SCREEN, &mut |window, area| vec![window.to_string().repeat(area.width); area.height]);
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], "0000000000│1111111111");
+        assert_eq!(lines[5], "0000000000│──────────");
+        assert_eq!(lines[10], "0000000000│2222222222");
+    }
+
+    #[test]
+    fn finds_neighbors_and_resizes()
+    {
+        let mut layout = three_windows();
+        assert_eq!(layout.neighbor(SCREEN, 0, Side::Right), Some(1));
+        assert_eq!(layout.neighbor(SCREEN, 2, Side::Left), Some(0));
+        assert_eq!(layout.neighbor(SCREEN, 2, Side::Above), Some(1));
+        assert_eq!(layout.neighbor(SCREEN, 0, Side::Left), None);
+
+        assert!(layout.resize(2, SplitDirection::Horizontal, 20));
+        assert_eq!(layout.areas(SCREEN)[1].1.height, 3);
+        assert!(layout.resize(2, SplitDirection::Vertical, 20));
+        assert_eq!(layout.areas(SCREEN)[0].1.width, 6);
+        assert!(layout.resize(0, SplitDirection::Vertical, 100));
+        assert_eq!(layout.areas(SCREEN)[0].1.width, 18);
+        assert!(!layout.resize(0, SplitDirection::Horizontal, 10));
+    }
+
+    #[test]
+    fn closing_gives_the_space_to_the_sibling()
+    {
+        let mut layout = three_windows();
+        assert_eq!(layout.close(1), Some(1));
+        assert_eq!(layout.areas(SCREEN), [
+            (0, Rect { x: 0, y: 0, width: 10, height: 11 }),
+            (1, Rect { x: 11, y: 0, width: 10, height: 11 })
+        ]);
+        assert_eq!(layout.close(0), Some(0));
+        assert_eq!(layout.close(0), None);
+        assert_eq!(layout.areas(SCREEN), [(0, SCREEN)]);
+    }
+}
@@ -0,0 +1,64 @@
+//! The core of the hecto text editor.
+//!
+//! A `Document` holds the rows of a buffer and implements the editing
+//! commands on it (insertion, deletion, indentation, comments, undo, search,
+//! bracket matching and saving), so it can be used without a terminal. An
+//! `Editor` shows a document on a `Terminal`, which draws through a `Backend`:
+//! `TermionBackend` for the real terminal, or `GridBackend` to render into a
+//! plain grid of text.
+//!
+//! ```
+//! use hecto::{Config, Document, Editor, Event, GridBackend, Key, Terminal};
+//!
+//! let backend = GridBackend::new(20, 5, Vec::new());
+//! let grid = backend.grid();
+//! let terminal = Terminal::new(Box::new(backend)).unwrap();
+//! let mut editor = Editor::new(terminal, Document::from_text("!\n"), Config::default());
+//! editor.process_event(Event::Key(Key::End)).unwrap();
+//! for c in "hi".chars()
+//! {
+//!     editor.process_event(Event::Key(Key::Char(c))).unwrap();
+//! }
+//! editor.refresh_screen().unwrap();
+//! assert_eq!(editor.document().text(), "!hi\n");
+//! assert_eq!(grid.borrow().lines[0], "!hi");
+//! ```
+#![warn(missing_docs)]
+
+mod backend;
+mod cells;
+mod cli;
+mod config;
+mod document;
+mod editor;
+mod files;
+mod filetype;
+mod layout;
+mod lock;
+mod row;
+mod status_line;
+mod terminal;
+pub use backend::Backend;
+pub use backend::Grid;
+pub use backend::GridBackend;
+pub use backend::TermionBackend;
+pub use cli::Args;
+pub use cli::USAGE;
+pub use config::Config;
+pub use config::SaveOptions;
+pub use document::DiskChange;
+pub use document::DiskState;
+pub use document::Document;
+pub use editor::Editor;
+pub use editor::Position;
+pub use editor::SearchDirection;
+pub use editor::VERSION;
+pub use files::Listing;
+pub use filetype::FileType;
+pub use lock::FileLock;
+pub use lock::LockOwner;
+pub use row::Row;
+pub use terminal::Event;
+pub use terminal::Size;
+pub use terminal::Terminal;
+pub use termion::event::{Key, MouseButton, MouseEvent};
@@ -0,0 +1,162 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const MARKER_SUFFIX: &str = ".hecto-lock";
+
+/// The hecto instance holding a `FileLock`.
+#[derive(Debug, PartialEq)]
+pub struct LockOwner
+{
+    /// The process ID of the instance.
+    pub pid: u32,
+    /// The user running it.
+    pub user: String
+}
+
+impl LockOwner
+{
+    fn current() -> Self
+    {
+        Self {
+            pid: process::id(),
+            user: env::var("USER")
+                .or_else(|_| env::var("LOGNAME"))
+                // SAFETY: `getuid` cannot fail.
+                .unwrap_or_else(|_| format!("uid {}", unsafe { libc::getuid() }))
+        }
+    }
+
+    fn parse(contents: &str) -> Option<Self>
+    {
+        let mut lines = contents.lines();
+        Some(Self {
+            pid: lines.next()?.trim().parse().ok()?,
+            user: lines.next().unwrap_or("unknown").trim().to_string()
+        })
+    }
+
+    // Only meaningful for processes on this machine, which is where the
+    // lock files of a shared box are created.
+    fn is_alive(&self) -> bool
+    {
+        let Ok(pid) = libc::pid_t::try_from(self.pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks whether the process exists.
+        unsafe { libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+    }
+}
+
+/// An advisory marker next to a file, telling other hecto instances that it
+/// is being edited. The marker is removed when the lock is dropped.
+#[derive(Debug)]
+pub struct FileLock
+{
+    path: PathBuf
+}
+
+impl FileLock
+{
+    /// Creates the marker for `filename`, replacing one left behind by a
+    /// process that no longer runs. Fails with the owner if a running
+    /// instance holds it, or with `None` if no marker could be created.
+    pub fn acquire(filename: &str) -> Result<Self, Option<LockOwner>>
+    {
+        let path = Self::path_for(Path::new(filename)).ok_or(None)?;
+        // The marker is written under a name of its own and then linked into
+        // place, so that another instance never reads a marker whose owner
+        // has not been written yet.
+        let owner = LockOwner::current();
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(format!(".{}", owner.pid));
+        let temporary = PathBuf::from(temporary);
+        fs::write(&temporary, format!("{}\n{}\n", owner.pid, owner.user)).map_err(|_| None)?;
+        let result = Self::link(&temporary, path);
+        let _ = fs::remove_file(&temporary);
+        result
+    }
+
+    fn link(temporary: &Path, path: PathBuf) -> Result<Self, Option<LockOwner>>
+    {
+        for _ in 0..2
+        {
+            match fs::hard_link(temporary, &path)
+            {
+                Ok(()) => return Ok(Self { path }),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(&path).ok().and_then(|contents| LockOwner::parse(&contents));
+                    match owner
+                    {
+                        Some(owner) if owner.is_alive() => return Err(Some(owner)),
+                        _ => fs::remove_file(&path).map_err(|_| None)?,
+                    }
+                },
+                Err(_) => return Err(None),
+            }
+        }
+        Err(None)
+    }
+
+    // Whether `name` is the file name of a marker, which browsing hides.
+    pub(crate) fn is_marker(name: &str) -> bool
+    {
+        name.starts_with('.') && name.ends_with(MARKER_SUFFIX)
+    }
+
+    // `dir/.name.hecto-lock` for `dir/name`.
+    fn path_for(file: &Path) -> Option<PathBuf>
+    {
+        let name = file.file_name()?.to_str()?;
+        Some(file.with_file_name(format!(".{}{}", name, MARKER_SUFFIX)))
+    }
+}
+
+impl Drop for FileLock
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn marks_a_file_until_dropped()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        let marker = dir.path().join(".notes.txt.hecto-lock");
+        let lock = FileLock::acquire(file.to_str().unwrap()).unwrap();
+        assert!(marker.exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        let owner = FileLock::acquire(file.to_str().unwrap()).unwrap_err().unwrap();
+        assert_eq!(owner.pid, process::id());
+        drop(lock);
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn replaces_markers_of_dead_processes()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        let marker = dir.path().join(".notes.txt.hecto-lock");
+        fs::write(&marker, format!("{}\nghost\n", i32::MAX)).unwrap();
+        let _lock = FileLock::acquire(file.to_str().unwrap()).unwrap();
+        let owner = LockOwner::parse(&fs::read_to_string(&marker).unwrap()).unwrap();
+        assert_eq!(owner.pid, process::id());
+    }
+
+    #[test]
+    fn fails_quietly_without_a_writable_directory()
+    {
+        assert_eq!(FileLock::acquire("/nonexistent/dir/file.txt").unwrap_err(), None);
+    }
+}
@@ -1,15 +1,54 @@
-mod document;
-mod editor;
-mod row;
-mod terminal;
-use editor::Editor;
-pub use document::Document;
-pub use editor::Position;
-pub use editor::SearchDirection;
-pub use row::Row;
-pub use terminal::Terminal;
+use hecto::{Args, Editor, USAGE, VERSION};
+use std::env;
+use std::io::{self, Write};
+use std::process;
 
 fn main() 
 {
-    Editor::default().run();
+    let args = match Args::parse(env::args().skip(1))
+    {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("hecto: {}\n\n{}", error, USAGE);
+            process::exit(2);
+        },
+    };
+    if args.help
+    {
+        print!("{}", USAGE);
+        return;
+    }
+    if args.version
+    {
+        println!("hecto {}", VERSION);
+        return;
+    }
+    let mut editor = match Editor::open(&args)
+    {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("hecto: {}", error);
+            process::exit(1);
+        },
+    };
+    // Closing the editor puts the terminal back on the main screen in cooked
+    // mode before anything else is printed.
+    let result = editor.run();
+    let documents = editor.into_documents();
+    let result = result.and_then(|()| {
+        if args.stdout
+        {
+            let text = documents.get(args.stdout_buffer()).map(|document| document.text()).unwrap_or_default();
+            io::stdout().write_all(text.as_bytes())?;
+        }
+        Ok(())
+    });
+    // Dropping the documents removes their lock files, which `process::exit`
+    // would skip.
+    drop(documents);
+    if let Err(error) = result
+    {
+        eprintln!("hecto: {}", error);
+        process::exit(1);
+    }
 }
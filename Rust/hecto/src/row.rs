@@ -1,8 +1,16 @@
+use crate::cells;
 use crate::SearchDirection;
 use std::cmp;
+use termion::color;
 use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Default)]
+pub const TAB_WIDTH: usize = 4;
+const WHITESPACE_FG_COLOR: color::Rgb = color::Rgb(110, 110, 110);
+const TRAILING_BG_COLOR: color::Rgb = color::Rgb(120, 40, 40);
+const ESCAPE_FG_COLOR: color::Rgb = color::Rgb(230, 160, 40);
+
+/// One line of a document, indexed by grapheme cluster.
+#[derive(Default, Clone)]
 pub struct Row
 {
     string: String,
@@ -20,65 +28,172 @@ impl From<&str> for Row
     }
 }
 
+// Returns how a grapheme that is invisible or unsafe to print is shown in
+// visible-whitespace mode: caret notation for C0 controls, `<U+XXXX>` for
+// other controls and unusual spaces.
+fn escape(grapheme: &str) -> Option<String>
+{
+    let mut chars = grapheme.chars();
+    let c = chars.next()?;
+    if chars.next().is_some() || c == ' ' || c == '\t'
+    {
+        return None;
+    }
+    if c.is_ascii_control() && c != '\u{7f}'
+    {
+        return Some(format!("^{}", ((c as u8) + 64) as char));
+    }
+    let is_zero_width = matches!(c, '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}');
+    if c.is_control() || c.is_whitespace() || is_zero_width
+    {
+        return Some(format!("<U+{:04X}>", c as u32));
+    }
+    None
+}
+
+fn grapheme_width(grapheme: &str, show_whitespace: bool) -> usize
+{
+    if grapheme == "\t"
+    {
+        return TAB_WIDTH;
+    }
+    if show_whitespace
+    {
+        if let Some(escaped) = escape(grapheme)
+        {
+            return escaped.len();
+        }
+    }
+    cells::width(grapheme)
+}
+
 impl Row
 {
-    pub fn render(&self, start: usize, end: usize) -> String 
+    /// The graphemes in `start..end` as terminal output, with tabs expanded
+    /// and, if `show_whitespace` is set, whitespace and controls made visible.
+    pub fn render(&self, start: usize, end: usize, show_whitespace: bool) -> String
     {
         let end = cmp::min(end, self.string.len());
         let start = cmp::min(start, end);
+        let trailing_start = self.len - self.string.graphemes(true).rev().take_while(|g| g.trim().is_empty()).count();
         let mut result = String::new();
-        for grapheme in self.string[..]
+        for (index, grapheme) in self.string[..]
             .graphemes(true)
+            .enumerate()
             .skip(start)
             .take(end - start)
         {
+            if !show_whitespace
+            {
+                if grapheme == "\t"
+                {
+                    result.push_str(&" ".repeat(TAB_WIDTH));
+                }
+                else
+                {
+                    result.push_str(grapheme);
+                }
+                continue;
+            }
+
+            let is_trailing = index >= trailing_start;
+            if is_trailing
+            {
+                result.push_str(&color::Bg(TRAILING_BG_COLOR).to_string());
+            }
             if grapheme == "\t"
             {
-                result.push_str("    ")
+                result.push_str(&format!(
+                    "{}\u{2192}{}{}",
+                    color::Fg(WHITESPACE_FG_COLOR),
+                    " ".repeat(TAB_WIDTH - 1),
+                    color::Fg(color::Reset)
+                ));
+            }
+            else if grapheme == " "
+            {
+                result.push_str(&format!("{}\u{b7}{}", color::Fg(WHITESPACE_FG_COLOR), color::Fg(color::Reset)));
+            }
+            else if let Some(escaped) = escape(grapheme)
+            {
+                result.push_str(&format!("{}{}{}", color::Fg(ESCAPE_FG_COLOR), escaped, color::Fg(color::Reset)));
             }
             else
             {
-                result.push_str(grapheme)
+                result.push_str(grapheme);
+            }
+            if is_trailing
+            {
+                result.push_str(&color::Bg(color::Reset).to_string());
             }
         }
         result
     }
 
+    /// Number of screen columns taken by the graphemes in `start..end`.
+    pub fn width(&self, start: usize, end: usize, show_whitespace: bool) -> usize
+    {
+        self.string[..]
+            .graphemes(true)
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .map(|grapheme| grapheme_width(grapheme, show_whitespace))
+            .sum()
+    }
+
+    /// Index of the grapheme covering screen column `column`, counting from
+    /// the grapheme at `start`, or the row length if the row is shorter.
+    pub fn index_at_column(&self, start: usize, column: usize, show_whitespace: bool) -> usize
+    {
+        let mut width = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate().skip(start)
+        {
+            width += grapheme_width(grapheme, show_whitespace);
+            if width > column
+            {
+                return index;
+            }
+        }
+        self.len
+    }
+
+    /// The number of grapheme clusters.
     pub fn len(&self) -> usize
     {
         self.len
     }
 
+    /// Whether the row has no graphemes.
     pub fn is_empty(&self) -> bool
     {
         self.string.is_empty()
     }
 
+    /// Inserts `c` before grapheme `at`, or appends it past the end.
     pub fn insert(&mut self, at: usize, c: char)
     {
         if at >= self.len()
         {
             self.string.push(c);
-            self.len += 1;
-            return;
         }
-
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate()
+        else
         {
-            if index == at
+            let mut result: String = String::new();
+            for (index, grapheme) in self.string[..].graphemes(true).enumerate()
             {
-                length += 1;
-                result.push(c);
+                if index == at
+                {
+                    result.push(c);
+                }
+                result.push_str(grapheme);
             }
-            length += 1;
-            result.push_str(grapheme);
+            self.string = result;
         }
-        self.len = length;
-        self.string = result;
+        // A combining character joins its neighbour instead of adding a grapheme.
+        self.len = self.string.graphemes(true).count();
     }
 
+    /// Removes the grapheme at `at`, if there is one.
     pub fn delete(&mut self, at: usize)
     {
         if at >= self.len()
@@ -87,60 +202,158 @@ impl Row
         }
 
         let mut result: String = String::new();
-        let mut length = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate()
         {
             if index != at
             {
-                length += 1;
                 result.push_str(grapheme);
             }
         }
-        self.len = length;
         self.string = result;
+        // The graphemes on either side of the deleted one may now combine.
+        self.len = self.string.graphemes(true).count();
     }
 
+    /// Adds `new` to the end of the row.
     pub fn append(&mut self, new: &Self)
     {
         self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len;
+        self.len = self.string.graphemes(true).count();
     }
 
+    /// Truncates the row at grapheme `at` and returns the rest.
     pub fn split(&mut self, at: usize) -> Self
     {
         let mut row: String = String::new();
-        let mut length = 0;
         let mut splitted_row: String = String::new();
-        let mut splited_length = 0;
         for (index, grapheme) in self.string[..].graphemes(true).enumerate()
         {
             if index < at
             {
-                length += 1;
                 row.push_str(grapheme);
             }
             else
             {
-                splited_length += 1;
                 splitted_row.push_str(grapheme);
             }
         }
 
+        // Either half can segment differently on its own, e.g. a combining
+        // mark left at the start of the new row.
+        self.len = row.graphemes(true).count();
         self.string = row;
-        self.len = length;
 
         Self {
-            string: splitted_row,
-            len: splited_length
+            len: splitted_row.graphemes(true).count(),
+            string: splitted_row
         }
     }
 
+    fn byte_index(&self, at: usize) -> usize
+    {
+        self.string
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
+
+    /// Inserts `string` before grapheme `at`, or appends it past the end.
+    pub fn insert_str(&mut self, at: usize, string: &str)
+    {
+        let index = self.byte_index(at);
+        self.string.insert_str(index, string);
+        self.len = self.string.graphemes(true).count();
+    }
+
+    /// Removes up to `count` graphemes starting at `at`.
+    pub fn remove(&mut self, at: usize, count: usize)
+    {
+        let start = self.byte_index(at);
+        let end = self.byte_index(at.saturating_add(count));
+        self.string.replace_range(start..end, "");
+        self.len = self.string.graphemes(true).count();
+    }
+
+    /// Prepends `unit` to the row.
+    pub fn indent(&mut self, unit: &str)
+    {
+        self.string.insert_str(0, unit);
+        self.len = self.string.graphemes(true).count();
+    }
+
+    /// Removes one leading tab, or up to `width` leading spaces, and returns
+    /// how many graphemes were removed.
+    pub fn dedent(&mut self, width: usize) -> usize
+    {
+        let removed = if self.string.starts_with('\t')
+        {
+            1
+        }
+        else
+        {
+            self.string.chars().take(width).take_while(|c| *c == ' ').count()
+        };
+        self.string.replace_range(..removed, "");
+        self.len = self.string.graphemes(true).count();
+        removed
+    }
+
+    /// The grapheme clusters of the row.
+    pub fn graphemes(&self) -> unicode_segmentation::Graphemes<'_>
+    {
+        self.string.graphemes(true)
+    }
+
+    /// Returns whether any trailing whitespace was removed.
+    pub fn trim_end(&mut self) -> bool
+    {
+        let trimmed_len = self.string.trim_end().len();
+        if trimmed_len == self.string.len()
+        {
+            return false;
+        }
+        self.string.truncate(trimmed_len);
+        self.len = self.string.graphemes(true).count();
+        true
+    }
+
+    /// Replaces tabs with spaces up to the next multiple of `width` and
+    /// returns whether the row contained any tabs.
+    pub fn expand_tabs(&mut self, width: usize) -> bool
+    {
+        if !self.string.contains('\t')
+        {
+            return false;
+        }
+        let mut result = String::new();
+        let mut column = 0;
+        for grapheme in self.string.graphemes(true)
+        {
+            if grapheme == "\t"
+            {
+                let spaces = width - column % width;
+                result.push_str(&" ".repeat(spaces));
+                column += spaces;
+            }
+            else
+            {
+                result.push_str(grapheme);
+                column += 1;
+            }
+        }
+        self.len = result.graphemes(true).count();
+        self.string = result;
+        true
+    }
+
+    /// The row as UTF-8 text.
     pub fn as_bytes(&self) -> &[u8]
     {
         self.string.as_bytes()
     }
 
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> 
+    /// The grapheme index of the next match of `query` from `at`.
+    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize>
     {
         if at > self.len 
         {
@@ -189,4 +402,151 @@ impl Row
         }
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn text(row: &Row) -> &str
+    {
+        std::str::from_utf8(row.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn counts_graphemes_not_bytes_or_chars()
+    {
+        let row = Row::from("he\u{301}llo 👍🏽");
+        assert_eq!(row.len(), 7);
+        assert!(!row.is_empty());
+        assert!(Row::default().is_empty());
+    }
+
+    #[test]
+    fn inserts_around_multibyte_graphemes()
+    {
+        let mut row = Row::from("añb");
+        row.insert(2, 'ü');
+        assert_eq!(text(&row), "añüb");
+        row.insert(0, '>');
+        row.insert(99, '<');
+        assert_eq!(text(&row), ">añüb<");
+        assert_eq!(row.len(), 6);
+    }
+
+    #[test]
+    fn deletes_whole_graphemes()
+    {
+        let mut row = Row::from("a👍🏽b");
+        row.delete(1);
+        assert_eq!(text(&row), "ab");
+        row.delete(2);
+        assert_eq!(text(&row), "ab");
+        row.delete(1);
+        assert_eq!((text(&row), row.len()), ("a", 1));
+    }
+
+    #[test]
+    fn splits_at_edges_and_in_the_middle()
+    {
+        let mut row = Row::from("ça va");
+        let tail = row.split(2);
+        assert_eq!((text(&row), row.len()), ("ça", 2));
+        assert_eq!((text(&tail), tail.len()), (" va", 3));
+
+        let mut row = Row::from("ab");
+        let tail = row.split(0);
+        assert_eq!((text(&row), text(&tail)), ("", "ab"));
+        let mut row = Row::from("ab");
+        let tail = row.split(2);
+        assert_eq!((text(&row), text(&tail)), ("ab", ""));
+        assert!(tail.is_empty());
+    }
+
+    #[test]
+    fn appends_rows()
+    {
+        let mut row = Row::from("née");
+        row.append(&Row::from(" ok"));
+        assert_eq!((text(&row), row.len()), ("née ok", 6));
+    }
+
+    #[test]
+    fn finds_grapheme_index_in_both_directions()
+    {
+        let row = Row::from("日本 abc 日本 abc");
+        assert_eq!(row.find("abc", 0, SearchDirection::Forward), Some(3));
+        assert_eq!(row.find("abc", 4, SearchDirection::Forward), Some(10));
+        assert_eq!(row.find("abc", 13, SearchDirection::Backward), Some(10));
+        assert_eq!(row.find("abc", 12, SearchDirection::Backward), Some(3));
+        assert_eq!(row.find("abc", 5, SearchDirection::Backward), None);
+        assert_eq!(row.find("xyz", 0, SearchDirection::Forward), None);
+        assert_eq!(row.find("abc", 14, SearchDirection::Forward), None);
+    }
+
+    #[test]
+    fn renders_tabs_and_clamps_range()
+    {
+        let row = Row::from("\tab");
+        assert_eq!(row.render(0, 10, false), "    ab");
+        assert_eq!(row.render(1, 2, false), "a");
+        assert_eq!(row.render(5, 2, false), "");
+        assert_eq!(row.width(0, 3, false), 6);
+        assert_eq!(row.index_at_column(0, 3, false), 0);
+        assert_eq!(row.index_at_column(0, 4, false), 1);
+        assert_eq!(row.index_at_column(0, 40, false), 3);
+        let wide = Row::from("日本x");
+        assert_eq!(wide.width(0, 3, false), 5);
+        assert_eq!(wide.index_at_column(0, 3, false), 1);
+    }
+
+    #[test]
+    fn renders_visible_whitespace_and_escapes()
+    {
+        let row = Row::from("a\u{a0}\u{1}");
+        let rendered = row.render(0, 3, true);
+        assert!(rendered.contains("<U+00A0>"));
+        assert!(rendered.contains("^A"));
+        assert_eq!(row.width(0, 3, true), 1 + 8 + 2);
+    }
+
+    #[test]
+    fn indents_and_dedents()
+    {
+        let mut row = Row::from("x");
+        row.indent("  ");
+        assert_eq!(text(&row), "  x");
+        assert_eq!(row.dedent(4), 2);
+        assert_eq!(row.dedent(4), 0);
+        let mut row = Row::from("\t\tx");
+        assert_eq!(row.dedent(4), 1);
+        assert_eq!((text(&row), row.len()), ("\tx", 2));
+    }
+
+    #[test]
+    fn indenting_before_a_combining_mark_recounts()
+    {
+        let mut row = Row::from("\u{301}x");
+        assert_eq!(row.len(), 2);
+        row.indent(" ");
+        assert_eq!(row.len(), 2);
+        assert_eq!(row.dedent(4), 1);
+        assert_eq!(row.len(), 2);
+    }
+
+    #[test]
+    fn trims_and_expands_tabs()
+    {
+        let mut row = Row::from("a b \t ");
+        assert!(row.trim_end());
+        assert!(!row.trim_end());
+        assert_eq!((text(&row), row.len()), ("a b", 3));
+
+        let mut row = Row::from("ab\tc\td");
+        assert!(row.expand_tabs(4));
+        assert_eq!(text(&row), "ab  c   d");
+        assert_eq!(row.len(), 9);
+        assert!(!row.expand_tabs(4));
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::path::Path;
+
+// Splits a status line format into what is aligned left and right.
+const ALIGN_RIGHT: &str = "{=}";
+
+pub const DEFAULT_FORMAT: &str = "{name}{readonly} - {lines} lines{modified}{buffers}{selection}{=}{filetype} | {line}/{lines}";
+
+// The left and right part of `format` with every `{segment}` replaced by
+// `value(segment)`. Unknown segments are kept as they are.
+pub fn expand<F>(format: &str, value: F) -> (String, String)
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let (left, right) = format.split_once(ALIGN_RIGHT).unwrap_or((format, ""));
+    (expand_segments(left, &value), expand_segments(right, &value))
+}
+
+fn expand_segments<F>(format: &str, value: &F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('{')
+    {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let segment = rest[1..].find('}').and_then(|end| Some((end, value(&rest[1..=end])?)));
+        match segment
+        {
+            Some((end, text)) => {
+                expanded.push_str(&text);
+                rest = &rest[end + 2..];
+            },
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+// The checked out branch of the git repository containing `dir`, or the
+// abbreviated commit if the HEAD is detached.
+pub fn git_branch(dir: &Path) -> Option<String>
+{
+    let dir = fs::canonicalize(dir).ok()?;
+    let git = dir.ancestors().map(|dir| dir.join(".git")).find(|git| git.exists())?;
+    // Worktrees and submodules have a `.git` file pointing to the real one.
+    let git = match fs::read_to_string(&git)
+    {
+        Ok(contents) => git.parent()?.join(contents.strip_prefix("gitdir:")?.trim()),
+        Err(_) => git,
+    };
+    let head = fs::read_to_string(git.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ")
+    {
+        Some(reference) => Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn expands_segments_on_both_sides()
+    {
+        let value = |segment: &str| match segment
+        {
+            "name" => Some("a.rs".to_string()),
+            "line" => Some("7".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand("{name}{=}{line}", value), ("a.rs".to_string(), "7".to_string()));
+        assert_eq!(expand("{ {name} {nope}} {", value), ("{ a.rs {nope}} {".to_string(), String::new()));
+    }
+
+    #[test]
+    fn reads_the_git_branch()
+    {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(git_branch(dir.path()), None);
+        fs::create_dir_all(dir.path().join("repo/.git")).unwrap();
+        fs::create_dir(dir.path().join("repo/src")).unwrap();
+        fs::write(dir.path().join("repo/.git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(git_branch(&dir.path().join("repo/src")).as_deref(), Some("feature/x"));
+        fs::write(dir.path().join("repo/.git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(git_branch(&dir.path().join("repo")).as_deref(), Some("0123456"));
+
+        fs::create_dir(dir.path().join("worktree")).unwrap();
+        fs::write(dir.path().join("worktree/.git"), "gitdir: ../repo/.git\n").unwrap();
+        assert_eq!(git_branch(&dir.path().join("worktree")).as_deref(), Some("0123456"));
+    }
+}
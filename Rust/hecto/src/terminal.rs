@@ -1,101 +1,127 @@
+use crate::backend::{Backend, TermionBackend};
 use crate::Position;
-use std::io::{self, stdout, Write};
-use termion::{color, event::Key, input::TermRead, raw::{IntoRawMode, RawTerminal}, style::Invert};
+use termion::event::{Key, MouseEvent};
 
+/// The part of the screen available to the document, in cells.
 pub struct Size
 {
+    /// Width in cells.
     pub width: u16,
+    /// Height in rows.
     pub height: u16
 }
 
+/// An input event delivered by a `Backend`.
+pub enum Event
+{
+    /// A key was pressed.
+    Key(Key),
+    /// The mouse was clicked, dragged or scrolled.
+    Mouse(MouseEvent),
+    /// The terminal was resized.
+    Resize,
+    /// The terminal window was focused.
+    Focus,
+    /// No input arrived for a while.
+    Idle
+}
+
+/// Sends frames to a `Backend`, redrawing only the lines that changed.
 pub struct Terminal
 {
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>
+    top_lines: u16,
+    backend: Box<dyn Backend>,
+    previous_frame: Vec<String>
 }
 
 impl Terminal
 {
+    /// A terminal drawing to stdout through a `TermionBackend`.
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self, std::io::Error>
     {
-        let size = termion::terminal_size()?;
-        Ok(Self {
-            size: Size {
-                width: size.0,
-                height: size.1.saturating_sub(2)
-            },
-            _stdout: stdout().into_raw_mode()?
-        })
+        Self::new(Box::new(TermionBackend::new()?))
     }
 
-    pub fn size(&self) -> &Size 
+    /// A terminal drawing through any `backend`, e.g. a `GridBackend`.
+    pub fn new(backend: Box<dyn Backend>) -> Result<Self, std::io::Error>
     {
-        &self.size
+        Ok(Self {
+            size: Self::text_area(backend.as_ref(), 0)?,
+            top_lines: 0,
+            backend,
+            previous_frame: Vec::new()
+        })
     }
 
-    pub fn cursor_hide()
+    // The part of the screen left for the document once `top_lines` and the
+    // status and message bars are drawn.
+    fn text_area(backend: &dyn Backend, top_lines: u16) -> Result<Size, std::io::Error>
     {
-        print!("{}", termion::cursor::Hide);
+        let size = backend.size()?;
+        Ok(Size {
+            width: size.0,
+            height: size.1.saturating_sub(2).saturating_sub(top_lines)
+        })
     }
 
-    pub fn cursor_show()
+    /// The size of the text area, excluding the top lines and the status and
+    /// message bars.
+    pub fn size(&self) -> &Size
     {
-        print!("{}", termion::cursor::Show);
+        &self.size
     }
 
-    pub fn clear_screen() 
-    {            
-        print!("{}", termion::clear::All);            
-    }      
-    
-    pub fn clear_current_line()
-    {
-        print!("{}", termion::clear::CurrentLine);
-    }
-    
-    pub fn set_bg_color(color: color::Rgb)
+    /// The number of lines above the text area, e.g. for a tab bar.
+    pub fn top_lines(&self) -> u16
     {
-        print!("{}", color::Bg(color));
+        self.top_lines
     }
 
-    pub fn reset_bg_color()
+    /// Reserves `lines` above the text area, and returns whether that changed
+    /// its size.
+    pub fn set_top_lines(&mut self, lines: u16) -> Result<bool, std::io::Error>
     {
-        print!("{}", color::Bg(color::Reset));
+        if lines == self.top_lines
+        {
+            return Ok(false);
+        }
+        self.top_lines = lines;
+        self.size = Self::text_area(self.backend.as_ref(), lines)?;
+        Ok(true)
     }
 
-    pub fn set_fg_color(color: color::Rgb)
+    /// Sends only the lines of `frame` that differ from the previously drawn
+    /// frame to the backend.
+    pub fn draw(&mut self, frame: &[String], cursor: &Position) -> Result<(), std::io::Error>
     {
-        print!("{}", color::Fg(color));
+        let clear = self.previous_frame.len() != frame.len();
+        if clear
+        {
+            self.previous_frame.clear();
+        }
+        let changed: Vec<(usize, &str)> = frame
+            .iter()
+            .enumerate()
+            .filter(|(y, line)| self.previous_frame.get(*y) != Some(line))
+            .map(|(y, line)| (y, line.as_str()))
+            .collect();
+        self.backend.draw(clear, &changed, cursor)?;
+        self.previous_frame = frame.to_vec();
+        Ok(())
     }
 
-    pub fn reset_fg_color()
+    /// Waits for the next key press, mouse event or resize. The size is already updated
+    /// when `Event::Resize` is returned.
+    pub fn read_event(&mut self) -> Result<Event, std::io::Error>
     {
-        print!("{}", color::Fg(color::Reset));
+        let event = self.backend.read_event()?;
+        if let Event::Resize = event
+        {
+            self.size = Self::text_area(self.backend.as_ref(), self.top_lines)?;
+            self.previous_frame.clear();
+        }
+        Ok(event)
     }
-
-    pub fn cursor_position(position: &Position) 
-    {            
-        let Position {mut x, mut y} = position;
-        x = x.saturating_add(1);
-        y = y.saturating_add(1);
-        let x = x as u16;           
-        let y = y as u16;           
-        print!("{}", termion::cursor::Goto(x, y));            
-    }            
-    
-    pub fn flush() -> Result<(), std::io::Error> 
-    {            
-        io::stdout().flush()            
-    }            
-    
-    pub fn read_key() -> Result<Key, std::io::Error> 
-    {            
-        loop 
-        {            
-            if let Some(key) = io::stdin().lock().keys().next() 
-            {            
-                return key;            
-            }            
-        }            
-    }
-}
\ No newline at end of file
+}